@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// Number of alarm events kept for the API history
+const ALARM_HISTORY_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AlarmCode {
+    Stall,
+    Overtravel,
+    SafeStopTimeout,
+    Interlock,
+    EncoderError,
+    StorageFailure,
+    /// Shared state was recovered after a panic; needs a restart to clear
+    InternalError,
+    /// A bend ended outside its accept tolerance
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Reported and latched, but the machine keeps running
+    Warning,
+    /// Blocks the output and aborts the job until acknowledged
+    Fault,
+}
+
+impl AlarmCode {
+    pub fn severity(self) -> Severity {
+        match self {
+            AlarmCode::Stall => Severity::Warning,
+            AlarmCode::Overtravel => Severity::Warning,
+            AlarmCode::SafeStopTimeout => Severity::Fault,
+            AlarmCode::Interlock => Severity::Fault,
+            AlarmCode::EncoderError => Severity::Fault,
+            AlarmCode::StorageFailure => Severity::Warning,
+            AlarmCode::InternalError => Severity::Fault,
+            AlarmCode::Reject => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alarm {
+    pub code: AlarmCode,
+    pub severity: Severity,
+    pub message: String,
+    pub raised_at_ms: u64,
+    /// Number of times the condition has been raised since the alarm latched
+    pub count: u32,
+    /// The condition that raised the alarm is still present
+    pub condition_active: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum AlarmEventKind {
+    Raised,
+    Cleared,
+    Acknowledged,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AlarmEvent {
+    pub code: AlarmCode,
+    pub kind: AlarmEventKind,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AlarmStatus {
+    pub blocking: bool,
+    pub alarms: Vec<Alarm>,
+    pub history: Vec<AlarmEvent>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmError {
+    /// The alarm cannot be acknowledged while its condition is still present
+    ConditionActive(AlarmCode),
+    /// There is no latched alarm with this code
+    NotLatched(AlarmCode),
+}
+
+impl fmt::Display for AlarmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlarmError::ConditionActive(code) => write!(f, "{:?} condition is still active", code),
+            AlarmError::NotLatched(code) => write!(f, "{:?} alarm is not latched", code),
+        }
+    }
+}
+
+/// Latching alarm list. An alarm stays latched after its condition clears and
+/// is only removed by an acknowledge once the condition is gone.
+#[derive(Debug, Default)]
+pub struct AlarmManager {
+    alarms: Vec<Alarm>,
+    history: VecDeque<AlarmEvent>,
+}
+
+impl AlarmManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raise an alarm condition. Returns `true` for a new occurrence, `false`
+    /// if the condition was already active.
+    pub fn raise(&mut self, code: AlarmCode, message: impl Into<String>, timestamp_ms: u64) -> bool {
+        if let Some(alarm) = self.alarms.iter_mut().find(|a| a.code == code) {
+            if alarm.condition_active {
+                return false;
+            }
+            alarm.condition_active = true;
+            alarm.count = alarm.count.saturating_add(1);
+            alarm.message = message.into();
+            alarm.raised_at_ms = timestamp_ms;
+        } else {
+            self.alarms.push(Alarm {
+                code,
+                severity: code.severity(),
+                message: message.into(),
+                raised_at_ms: timestamp_ms,
+                count: 1,
+                condition_active: true,
+            });
+        }
+        self.record(code, AlarmEventKind::Raised, timestamp_ms);
+        true
+    }
+
+    /// Mark the condition behind an alarm as gone. The alarm stays latched.
+    pub fn clear_condition(&mut self, code: AlarmCode, timestamp_ms: u64) {
+        if let Some(alarm) = self.alarms.iter_mut().find(|a| a.code == code && a.condition_active) {
+            alarm.condition_active = false;
+            self.record(code, AlarmEventKind::Cleared, timestamp_ms);
+        }
+    }
+
+    pub fn acknowledge(&mut self, code: AlarmCode, timestamp_ms: u64) -> Result<(), AlarmError> {
+        let pos = self.alarms.iter()
+            .position(|a| a.code == code)
+            .ok_or(AlarmError::NotLatched(code))?;
+        if self.alarms[pos].condition_active {
+            return Err(AlarmError::ConditionActive(code));
+        }
+        self.alarms.remove(pos);
+        self.record(code, AlarmEventKind::Acknowledged, timestamp_ms);
+        Ok(())
+    }
+
+    /// Acknowledge every alarm whose condition has cleared. Returns the codes
+    /// that could not be acknowledged because their condition is still present.
+    pub fn acknowledge_all(&mut self, timestamp_ms: u64) -> Vec<AlarmCode> {
+        let codes: Vec<AlarmCode> = self.alarms.iter().map(|a| a.code).collect();
+        codes.into_iter()
+            .filter(|&code| self.acknowledge(code, timestamp_ms).is_err())
+            .collect()
+    }
+
+    pub fn is_latched(&self, code: AlarmCode) -> bool {
+        self.alarms.iter().any(|a| a.code == code)
+    }
+
+    /// Any latched alarm with `Fault` severity
+    pub fn is_blocking(&self) -> bool {
+        self.alarms.iter().any(|a| a.severity == Severity::Fault)
+    }
+
+    pub fn latched_codes(&self) -> Vec<AlarmCode> {
+        self.alarms.iter().map(|a| a.code).collect()
+    }
+
+    pub fn status(&self) -> AlarmStatus {
+        AlarmStatus {
+            blocking: self.is_blocking(),
+            alarms: self.alarms.clone(),
+            history: self.history.iter().cloned().collect(),
+        }
+    }
+
+    fn record(&mut self, code: AlarmCode, kind: AlarmEventKind, timestamp_ms: u64) {
+        if self.history.len() == ALARM_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(AlarmEvent { code, kind, timestamp_ms });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raise_latches_alarm() {
+        let mut alarms = AlarmManager::new();
+        assert!(alarms.raise(AlarmCode::Stall, "no motion", 10));
+        assert!(alarms.is_latched(AlarmCode::Stall));
+    }
+
+    #[test]
+    fn raise_while_active_is_not_a_new_occurrence() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Stall, "no motion", 10);
+        assert!(!alarms.raise(AlarmCode::Stall, "no motion", 11));
+        assert_eq!(alarms.status().alarms[0].count, 1);
+    }
+
+    #[test]
+    fn alarm_stays_latched_after_condition_clears() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Interlock, "guard door open", 10);
+        alarms.clear_condition(AlarmCode::Interlock, 20);
+        assert!(alarms.is_latched(AlarmCode::Interlock));
+        assert!(alarms.is_blocking());
+    }
+
+    #[test]
+    fn acknowledge_rejected_while_condition_active() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Interlock, "guard door open", 10);
+        assert_eq!(
+            alarms.acknowledge(AlarmCode::Interlock, 20),
+            Err(AlarmError::ConditionActive(AlarmCode::Interlock))
+        );
+        assert!(alarms.is_latched(AlarmCode::Interlock));
+    }
+
+    #[test]
+    fn acknowledge_removes_cleared_alarm() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Interlock, "guard door open", 10);
+        alarms.clear_condition(AlarmCode::Interlock, 20);
+        assert_eq!(alarms.acknowledge(AlarmCode::Interlock, 30), Ok(()));
+        assert!(!alarms.is_latched(AlarmCode::Interlock));
+        assert!(!alarms.is_blocking());
+    }
+
+    #[test]
+    fn acknowledge_unknown_alarm_is_an_error() {
+        let mut alarms = AlarmManager::new();
+        assert_eq!(
+            alarms.acknowledge(AlarmCode::Stall, 10),
+            Err(AlarmError::NotLatched(AlarmCode::Stall))
+        );
+    }
+
+    #[test]
+    fn warnings_do_not_block() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::StorageFailure, "nvs write failed", 10);
+        assert!(!alarms.is_blocking());
+    }
+
+    #[test]
+    fn acknowledge_all_reports_alarms_still_active() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Stall, "no motion", 10);
+        alarms.clear_condition(AlarmCode::Stall, 15);
+        alarms.raise(AlarmCode::Interlock, "guard door open", 20);
+        assert_eq!(alarms.acknowledge_all(30), vec![AlarmCode::Interlock]);
+        assert_eq!(alarms.latched_codes(), vec![AlarmCode::Interlock]);
+    }
+
+    #[test]
+    fn reraise_after_clear_counts_occurrences() {
+        let mut alarms = AlarmManager::new();
+        alarms.raise(AlarmCode::Stall, "no motion", 10);
+        alarms.clear_condition(AlarmCode::Stall, 20);
+        assert!(alarms.raise(AlarmCode::Stall, "no motion", 30));
+        let status = alarms.status();
+        assert_eq!(status.alarms[0].count, 2);
+        assert_eq!(status.alarms[0].raised_at_ms, 30);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut alarms = AlarmManager::new();
+        for i in 0..(ALARM_HISTORY_LEN as u64) {
+            alarms.raise(AlarmCode::Stall, "no motion", i * 2);
+            alarms.clear_condition(AlarmCode::Stall, i * 2 + 1);
+        }
+        assert_eq!(alarms.status().history.len(), ALARM_HISTORY_LEN);
+    }
+}
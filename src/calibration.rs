@@ -0,0 +1,148 @@
+use crate::machine::MachineState;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Linear count-to-angle conversion measured on the machine:
+/// `angle = steps * degrees_per_step + offset`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Calibration {
+    pub degrees_per_step: f32,
+    /// Angle at a count of 0
+    pub offset: f32,
+}
+
+/// Raw count recorded with the ram at a known angle.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CalibrationPoint {
+    pub angle: f32,
+    pub steps: i32,
+}
+
+/// Steps of the two-point calibration routine. `point` is 1 or 2.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CalibrationCommand {
+    /// Record the current count as the given known angle
+    Capture { point: usize, angle: f32 },
+    /// Compute the calibration from both points and store it in the settings
+    Apply,
+    /// Remove the calibration and go back to the encoder resolution conversion
+    Clear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// Only available with no job running (Idle or Manual)
+    Busy(MachineState),
+    InvalidPoint(usize),
+    MissingPoint(usize),
+    /// The two points are too close together to give a usable scale
+    PointsTooClose,
+    /// The count goes down as the angle goes up; the forward direction is wrong
+    WrongDirection,
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::Busy(state) => write!(f, "Cannot calibrate while {:?}", state),
+            CalibrationError::InvalidPoint(point) => write!(f, "Calibration point must be 1 or 2, not {}", point),
+            CalibrationError::MissingPoint(point) => write!(f, "Calibration point {} has not been captured", point),
+            CalibrationError::PointsTooClose => write!(f, "Calibration points are too close together"),
+            CalibrationError::WrongDirection => write!(f, "Count decreases as the angle increases - check the forward direction"),
+        }
+    }
+}
+
+// Smallest spread between the two points, in degrees and in counts
+const MIN_SPAN_DEGREES: f32 = 10.0;
+const MIN_SPAN_STEPS: i32 = 10;
+
+impl Calibration {
+    pub fn from_points(a: CalibrationPoint, b: CalibrationPoint) -> Result<Self, CalibrationError> {
+        let steps = b.steps - a.steps;
+        let degrees = b.angle - a.angle;
+        if degrees.abs() < MIN_SPAN_DEGREES || steps.abs() < MIN_SPAN_STEPS {
+            return Err(CalibrationError::PointsTooClose);
+        }
+        let degrees_per_step = degrees / steps as f32;
+        if degrees_per_step < 0.0 {
+            return Err(CalibrationError::WrongDirection);
+        }
+        Ok(Self {
+            degrees_per_step,
+            offset: a.angle - a.steps as f32 * degrees_per_step,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.degrees_per_step.is_finite() && self.degrees_per_step > 0.0) {
+            return Err(format!("Calibration scale must be greater than 0°/step, not {}", self.degrees_per_step));
+        }
+        if !self.offset.is_finite() {
+            return Err("Calibration offset must be a number of degrees".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn steps_to_degrees(&self, steps: i32) -> f32 {
+        steps as f32 * self.degrees_per_step + self.offset
+    }
+
+    pub fn degrees_to_steps(&self, angle: f32) -> i32 {
+        ((angle - self.offset) / self.degrees_per_step).round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(angle: f32, steps: i32) -> CalibrationPoint {
+        CalibrationPoint { angle, steps }
+    }
+
+    #[test]
+    fn scale_and_offset_from_two_points() {
+        let calibration = Calibration::from_points(point(10.0, 24), point(90.0, 184)).unwrap();
+        assert_eq!(calibration.degrees_per_step, 0.5);
+        assert_eq!(calibration.offset, -2.0);
+        assert_eq!(calibration.steps_to_degrees(184), 90.0);
+        assert_eq!(calibration.degrees_to_steps(45.0), 94);
+    }
+
+    #[test]
+    fn points_may_be_captured_in_either_order() {
+        let forward = Calibration::from_points(point(0.0, 0), point(90.0, 100)).unwrap();
+        let reverse = Calibration::from_points(point(90.0, 100), point(0.0, 0)).unwrap();
+        assert_eq!(forward, reverse);
+    }
+
+    #[test]
+    fn close_points_are_rejected() {
+        assert_eq!(Calibration::from_points(point(10.0, 0), point(15.0, 100)), Err(CalibrationError::PointsTooClose));
+        assert_eq!(Calibration::from_points(point(0.0, 0), point(90.0, 5)), Err(CalibrationError::PointsTooClose));
+    }
+
+    #[test]
+    fn reversed_count_is_rejected() {
+        assert_eq!(Calibration::from_points(point(0.0, 100), point(90.0, 0)), Err(CalibrationError::WrongDirection));
+    }
+
+    #[test]
+    fn unusable_calibration_is_invalid() {
+        let calibration = |degrees_per_step, offset| Calibration { degrees_per_step, offset };
+        assert!(calibration(0.5, -2.0).validate().is_ok());
+        for degrees_per_step in [0.0, -0.5, f32::NAN, f32::INFINITY] {
+            assert!(calibration(degrees_per_step, 0.0).validate().is_err());
+        }
+        assert!(calibration(0.5, f32::NAN).validate().is_err());
+    }
+
+    #[test]
+    fn calibration_command_json() {
+        let parse = |json| serde_json::from_str::<CalibrationCommand>(json).unwrap();
+        assert_eq!(parse(r#"{"action":"capture","point":2,"angle":90}"#), CalibrationCommand::Capture { point: 2, angle: 90.0 });
+        assert_eq!(parse(r#"{"action":"apply"}"#), CalibrationCommand::Apply);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::alarm::{AlarmCode, AlarmError};
+use crate::calibration::{CalibrationCommand, CalibrationError};
+use crate::feedback::FeedbackError;
+use crate::machine::{IllegalTransition, MachineState};
+use crate::navigation::{NavigationError, StepCommand};
+use crate::program::{EditError, ProgramEdit};
+use crate::teach::{TeachError, TeachSource};
+use crate::rotary::{BendOptions, Settings, StartError};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Commands waiting for the control task; further commands are rejected
+const COMMAND_QUEUE_LEN: usize = 8;
+// The control task drains the queue every ~1 ms, so this only expires if it is stuck
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Requests from the web server that change the control state.
+#[derive(Clone, Debug)]
+pub enum Command {
+    Start { angles: Vec<f32>, bends: Vec<BendOptions> },
+    Stop,
+    AbortSafeStop,
+    /// Pause at the end of the current stroke
+    Pause,
+    Resume,
+    /// Repeat, skip or go back within the program (ram at the top only)
+    Step(StepCommand),
+    /// Change a bend that has not been executed yet
+    EditProgram(ProgramEdit),
+    /// Enabling starts a new, empty target list
+    SetTeachMode(bool),
+    /// Two-point angle calibration
+    Calibrate(CalibrationCommand),
+    /// Load the zero reference into the count at the current position
+    SetZero,
+    /// Clear the output-to-reversal latency statistics
+    ResetLatency,
+    /// Append an angle to the target list in teach mode
+    Teach(TeachSource),
+    /// Operator-measured angle of the last completed bend
+    Feedback(f32),
+    SetManual(bool),
+    UpdateSettings(Settings),
+    /// Acknowledge one alarm, or every cleared alarm when `None`
+    Acknowledge(Option<AlarmCode>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandError {
+    Start(StartError),
+    IllegalTransition(IllegalTransition),
+    Alarm(AlarmError),
+    AlarmsStillActive(Vec<AlarmCode>),
+    NoSafeStop,
+    Navigation(NavigationError),
+    Edit(EditError),
+    Teach(TeachError),
+    Calibration(CalibrationError),
+    Feedback(FeedbackError),
+    /// Zero can only be set with no stroke in progress
+    ZeroRejected(MachineState),
+    /// Resolution, calibration, direction and travel only change with no job loaded
+    ConversionLocked(MachineState),
+    /// The control task did not take the command (queue full or task stopped)
+    Unavailable,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Start(e) => write!(f, "{}", e),
+            CommandError::IllegalTransition(e) => write!(f, "{}", e),
+            CommandError::Alarm(e) => write!(f, "{}", e),
+            CommandError::AlarmsStillActive(codes) => write!(f, "Alarms still active: {:?}", codes),
+            CommandError::NoSafeStop => write!(f, "No safe stop in progress"),
+            CommandError::Navigation(e) => write!(f, "{}", e),
+            CommandError::Edit(e) => write!(f, "{}", e),
+            CommandError::Teach(e) => write!(f, "{}", e),
+            CommandError::Calibration(e) => write!(f, "{}", e),
+            CommandError::Feedback(e) => write!(f, "{}", e),
+            CommandError::ZeroRejected(state) => write!(f, "Cannot set zero while {:?}", state),
+            CommandError::ConversionLocked(state) => write!(
+                f,
+                "Cannot change resolution, calibration, direction or travel while {:?}",
+                state
+            ),
+            CommandError::Unavailable => write!(f, "Control task did not respond"),
+        }
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
+
+struct Envelope {
+    command: Command,
+    reply: SyncSender<CommandResult>,
+    /// Set by whichever side gets to it first: the control task before applying
+    /// the command, or the sender when it gives up waiting
+    claimed: Arc<AtomicBool>,
+}
+
+/// Web server side of the command channel.
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: SyncSender<Envelope>,
+}
+
+/// Control task side of the command channel.
+pub struct CommandReceiver {
+    rx: Receiver<Envelope>,
+}
+
+pub fn channel() -> (CommandSender, CommandReceiver) {
+    let (tx, rx) = mpsc::sync_channel(COMMAND_QUEUE_LEN);
+    (CommandSender { tx }, CommandReceiver { rx })
+}
+
+impl CommandSender {
+    /// Queue a command and wait until the control task has applied it.
+    pub fn send(&self, command: Command) -> CommandResult {
+        self.send_timeout(command, REPLY_TIMEOUT)
+    }
+
+    /// A command reported as `Unavailable` is never applied later: on timeout
+    /// it is withdrawn unless the control task has already taken it, in which
+    /// case its result is awaited.
+    fn send_timeout(&self, command: Command, timeout: Duration) -> CommandResult {
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        let claimed = Arc::new(AtomicBool::new(false));
+        self.tx
+            .try_send(Envelope { command, reply: reply_tx, claimed: claimed.clone() })
+            .map_err(|_| CommandError::Unavailable)?;
+        if let Ok(result) = reply_rx.recv_timeout(timeout) {
+            return result;
+        }
+        if !claimed.swap(true, Ordering::AcqRel) {
+            return Err(CommandError::Unavailable);
+        }
+        reply_rx.recv().unwrap_or(Err(CommandError::Unavailable))
+    }
+}
+
+impl CommandReceiver {
+    /// Apply every queued command in order and hand each result back to its sender.
+    /// Commands whose sender has already given up are dropped unapplied.
+    pub fn drain(&self, mut apply: impl FnMut(Command) -> CommandResult) {
+        for envelope in self.rx.try_iter() {
+            // Withdrawn by a sender that timed out
+            if envelope.claimed.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+            let result = apply(envelope.command);
+            // The sender may have gone away
+            let _ = envelope.reply.try_send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn result_is_returned_to_sender() {
+        let (sender, receiver) = channel();
+        let handle = thread::spawn(move || sender.send(Command::Stop));
+        let mut applied = Vec::new();
+        while !handle.is_finished() {
+            receiver.drain(|command| {
+                applied.push(format!("{:?}", command));
+                Err(CommandError::NoSafeStop)
+            });
+        }
+        assert_eq!(handle.join().unwrap(), Err(CommandError::NoSafeStop));
+        assert_eq!(applied, vec!["Stop".to_string()]);
+    }
+
+    #[test]
+    fn timed_out_command_is_not_applied() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            sender.send_timeout(Command::SetManual(true), Duration::from_millis(1)),
+            Err(CommandError::Unavailable)
+        );
+        let mut applied = 0;
+        receiver.drain(|_| {
+            applied += 1;
+            Ok(())
+        });
+        assert_eq!(applied, 0);
+    }
+
+    #[test]
+    fn full_queue_is_rejected() {
+        let (sender, _receiver) = channel();
+        for _ in 0..COMMAND_QUEUE_LEN {
+            let (reply, _) = mpsc::sync_channel(1);
+            let claimed = Arc::new(AtomicBool::new(false));
+            sender.tx.try_send(Envelope { command: Command::Stop, reply, claimed }).unwrap();
+        }
+        assert_eq!(sender.send(Command::Stop), Err(CommandError::Unavailable));
+    }
+
+    #[test]
+    fn stopped_control_task_is_reported() {
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.send(Command::Stop), Err(CommandError::Unavailable));
+    }
+}
@@ -0,0 +1,2301 @@
+use crate::alarm::AlarmCode;
+use crate::calibration::{Calibration, CalibrationCommand, CalibrationError, CalibrationPoint};
+use crate::command::{Command, CommandError, CommandResult};
+use crate::events::EventKind;
+use crate::feedback::{self, FeedbackError, FeedbackRecord};
+use crate::hysteresis::{Hysteresis, Thresholds};
+use crate::interlock::InterlockInput;
+use crate::latency::LatencyMeter;
+use crate::machine::{IllegalTransition, MachineState};
+use crate::navigation::{self, NavigationError, StepCommand, StepPosition};
+use crate::output::OutputMode;
+use crate::program::{EditError, ProgramEdit};
+use crate::results::BendResult;
+use crate::rotary::{BendOptions, OvertravelRecord, RotaryEncoderState, SafeStopOutcome, SafeStopReport, Settings};
+use crate::snapshot::ControlSnapshot;
+use crate::travel::StepRange;
+use crate::velocity::{self, VelocityEstimator};
+use crate::teach::{PeakTracker, TeachError, TeachSource};
+use log::*;
+use std::sync::atomic::Ordering;
+
+/// Inputs sampled by the rotary task once per loop iteration.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ControlInputs {
+    /// Encoder movement since the last iteration: 1, -1 or 0
+    pub direction: i32,
+    /// Guard door contact is open (raw input, before the enable setting is applied)
+    pub guard_door_open: bool,
+    /// Light curtain is interrupted (raw input, before the enable setting is applied)
+    pub light_curtain_open: bool,
+    /// Top-dead-centre reference input is active (raw input, before the enable setting)
+    pub reference_active: bool,
+}
+
+/// Output control state machine run by the rotary task on Core 1.
+///
+/// Kept free of GPIO types so the behaviour can be exercised by host tests:
+/// the task samples the pins, calls `step()` and drives the output pin with
+/// the returned level.
+///
+/// The controller keeps its own copy of the settings and the program and only
+/// re-reads them when their version changes, so a normal iteration takes no
+/// locks. Each iteration publishes a `ControlSnapshot` for the web server.
+pub struct Controller {
+    state: RotaryEncoderState,
+    settings: Settings,
+    settings_version: u32,
+    // Travel range of `settings` in counts
+    step_range: StepRange,
+    targets: Vec<i32>,
+    bend_options: Vec<BendOptions>,
+    program_version: u32,
+    // Interlock inputs after the enable settings, as of the last iteration
+    guard_door_open: bool,
+    light_curtain_open: bool,
+    encoder_error_raised: bool,
+    internal_error_raised: bool,
+    // Highest count seen since the output fired for the current bend
+    peak_steps: i32,
+    // Time the ram left the top for the current bend
+    stroke_started_ms: Option<u64>,
+    // Leaving the top to the trigger point, for the bend being returned from
+    time_to_target_ms: u32,
+    velocity: VelocityEstimator,
+    degrees_per_second: f32,
+    // Lead for the output latency at the current speed, while a job is running
+    lead_angle: f32,
+    // Output mode of the bend that fired last and when it fired
+    output_mode: OutputMode,
+    fired_ms: u64,
+    // The output turns off below this angle in Level and Dwell mode; set when it switches on
+    release_angle: Option<f32>,
+    // The output has switched off for the current bend and stays off until the next one
+    released: bool,
+    // Output level held by Toggle bends across strokes
+    toggle_on: bool,
+    // A manual output request arrived since the last iteration
+    manual_renewed: bool,
+    manual_keepalive_ms: u64,
+    // Time the manual output last switched on, while it is on
+    manual_on_since_ms: Option<u64>,
+    // Count change from the encoder this iteration, +1 forward
+    motion: i32,
+    latency: LatencyMeter,
+    overtravel_raised: bool,
+    // Time of the last encoder movement, or of the job start
+    last_motion_ms: u64,
+    idle_ms: u32,
+    was_active: bool,
+    safe_stop_started_ms: Option<u64>,
+    // Set at a reset, cleared once the ram goes above the re-arm angle or a new job starts
+    rearm_pending: bool,
+    // Pause requested mid-stroke; taken once the ram is back at the top
+    pause_requested: bool,
+    // Bend whose stroke finished last in this job, for Repeat
+    last_completed: Option<StepPosition>,
+    teach_mode: bool,
+    teach_peaks: PeakTracker,
+    // Last level of the reference input, to re-zero on its rising edge only
+    reference_active: bool,
+    calibration_points: [Option<CalibrationPoint>; 2],
+    snapshot: ControlSnapshot,
+}
+
+impl Controller {
+    pub fn new(state: RotaryEncoderState) -> Self {
+        let settings_version = state.settings_version();
+        let settings = state.get_settings();
+        let step_range = settings.step_range();
+        let program_version = state.program_version();
+        let mut targets = Vec::new();
+        let mut bend_options = Vec::new();
+        state.copy_program(&mut targets, &mut bend_options);
+        Self {
+            state,
+            settings,
+            settings_version,
+            step_range,
+            targets,
+            bend_options,
+            program_version,
+            guard_door_open: false,
+            light_curtain_open: false,
+            encoder_error_raised: false,
+            internal_error_raised: false,
+            peak_steps: 0,
+            stroke_started_ms: None,
+            time_to_target_ms: 0,
+            velocity: VelocityEstimator::default(),
+            degrees_per_second: 0.0,
+            lead_angle: 0.0,
+            output_mode: OutputMode::Level,
+            fired_ms: 0,
+            release_angle: None,
+            released: false,
+            toggle_on: false,
+            manual_renewed: false,
+            manual_keepalive_ms: 0,
+            manual_on_since_ms: None,
+            motion: 0,
+            latency: LatencyMeter::default(),
+            overtravel_raised: false,
+            last_motion_ms: 0,
+            idle_ms: 0,
+            was_active: false,
+            safe_stop_started_ms: None,
+            rearm_pending: false,
+            pause_requested: false,
+            last_completed: None,
+            teach_mode: false,
+            teach_peaks: PeakTracker::default(),
+            reference_active: false,
+            calibration_points: [None; 2],
+            snapshot: ControlSnapshot::default(),
+        }
+    }
+
+    /// Apply a command from the web server. The rotary task calls this at the
+    /// start of an iteration, before the inputs are sampled.
+    pub fn apply(&mut self, command: Command) -> CommandResult {
+        let encoder_state = &self.state;
+        match command {
+            Command::Start { angles, bends } => {
+                encoder_state.set_program(angles, bends).map_err(CommandError::Start)?;
+                self.last_completed = None;
+                self.teach_mode = false;
+                Ok(())
+            }
+            Command::Stop => {
+                encoder_state.stop();
+                Ok(())
+            }
+            Command::AbortSafeStop => {
+                if encoder_state.abort_safe_stop() {
+                    Ok(())
+                } else {
+                    Err(CommandError::NoSafeStop)
+                }
+            }
+            Command::Pause => self.pause(),
+            Command::Resume => self.resume(),
+            Command::Step(step) => self.navigate(step),
+            Command::EditProgram(edit) => self.edit_program(edit),
+            Command::SetTeachMode(enabled) => self.set_teach_mode(enabled),
+            Command::Calibrate(step) => self.calibrate(step).map_err(CommandError::Calibration),
+            Command::Feedback(measured_angle) => self.feedback(measured_angle).map_err(CommandError::Feedback),
+            Command::ResetLatency => {
+                self.latency.reset();
+                info!("⏱️ Latency statistics cleared");
+                Ok(())
+            }
+            Command::SetZero => {
+                let machine_state = encoder_state.get_machine_state();
+                if !matches!(machine_state, MachineState::Idle | MachineState::Manual | MachineState::Paused) {
+                    return Err(CommandError::ZeroRejected(machine_state));
+                }
+                let before = encoder_state.get_angle();
+                encoder_state.set_zero();
+                encoder_state.log_event(
+                    EventKind::ZeroSet,
+                    format!("Zero set at {:.1}° (now {:.1}°)", before, encoder_state.get_angle()),
+                    encoder_state.uptime_ms(),
+                );
+                Ok(())
+            }
+            Command::Teach(source) => self.teach(source),
+            Command::SetManual(on) => {
+                encoder_state.set_manual_output(on).map_err(CommandError::IllegalTransition)?;
+                // Every request renews the keep-alive
+                self.manual_renewed = true;
+                Ok(())
+            }
+            Command::UpdateSettings(settings) => {
+                // Like calibration: the running program's step targets must keep their meaning
+                let machine_state = encoder_state.get_machine_state();
+                if !matches!(machine_state, MachineState::Idle | MachineState::Manual)
+                    && !settings.same_conversion(&encoder_state.get_settings()) {
+                    return Err(CommandError::ConversionLocked(machine_state));
+                }
+                encoder_state.set_settings(settings);
+                Ok(())
+            }
+            Command::Acknowledge(Some(code)) => {
+                encoder_state.acknowledge_alarm(code).map_err(CommandError::Alarm)
+            }
+            Command::Acknowledge(None) => {
+                let still_active = encoder_state.acknowledge_all_alarms();
+                if still_active.is_empty() {
+                    Ok(())
+                } else {
+                    Err(CommandError::AlarmsStillActive(still_active))
+                }
+            }
+        }
+    }
+
+    /// Pause now if the ram is at the top, otherwise once the current stroke is done.
+    fn pause(&mut self) -> CommandResult {
+        match self.state.get_machine_state() {
+            MachineState::Armed => {
+                self.state.transition_from(MachineState::Armed, MachineState::Paused);
+                self.log_paused(self.state.uptime_ms());
+                Ok(())
+            }
+            MachineState::Bending | MachineState::Returning => {
+                self.pause_requested = true;
+                info!("⏸️ Pause requested - finishing current stroke");
+                Ok(())
+            }
+            MachineState::Paused => Ok(()),
+            from => Err(CommandError::IllegalTransition(IllegalTransition { from, to: MachineState::Paused })),
+        }
+    }
+
+    /// Resume a paused job, or cancel a pause that has not taken effect yet.
+    fn resume(&mut self) -> CommandResult {
+        let current = self.state.get_machine_state();
+        if current == MachineState::Paused && self.state.transition_from(current, MachineState::Armed) {
+            self.state.log_event(
+                EventKind::Resumed,
+                format!("Resumed at bend {}, run {}",
+                    self.state.get_current_target_index() + 1, self.state.get_current_run()),
+                self.state.uptime_ms(),
+            );
+            Ok(())
+        } else if current.is_running() && self.pause_requested {
+            self.pause_requested = false;
+            info!("▶️ Pending pause cancelled");
+            Ok(())
+        } else {
+            Err(CommandError::IllegalTransition(IllegalTransition { from: current, to: MachineState::Armed }))
+        }
+    }
+
+    /// Move to another bend or run. Only taken with the ram at the top, so the
+    /// bend in progress is never swapped out from under the output.
+    fn navigate(&mut self, step: StepCommand) -> CommandResult {
+        let machine_state = self.state.get_machine_state();
+        if !matches!(machine_state, MachineState::Armed | MachineState::Paused) {
+            return Err(CommandError::Navigation(NavigationError::NotAtTop(machine_state)));
+        }
+        // The program may have been replaced by a Start earlier in this drain
+        self.refresh();
+        let from = StepPosition {
+            index: self.state.get_current_target_index(),
+            run: self.state.get_current_run(),
+        };
+        let to = navigation::navigate(
+            step,
+            from,
+            self.last_completed,
+            self.targets.len(),
+            self.state.get_total_runs(),
+        )
+        .map_err(CommandError::Navigation)?;
+
+        self.state.set_current_run(to.run);
+        self.state.set_current_target_index(to.index);
+        self.state.log_event(
+            EventKind::StepChanged,
+            format!("{:?}: bend {}, run {} -> bend {}, run {}",
+                step, from.index + 1, from.run, to.index + 1, to.run),
+            self.state.uptime_ms(),
+        );
+        Ok(())
+    }
+
+    /// Change an upcoming bend. At the top of the stroke the next bend can still
+    /// be changed; mid-stroke only the bends after the one in progress.
+    fn edit_program(&mut self, edit: ProgramEdit) -> CommandResult {
+        let current = self.state.get_current_target_index();
+        let first_editable = match self.state.get_machine_state() {
+            MachineState::Armed | MachineState::Paused => current,
+            MachineState::Bending | MachineState::Returning => current + 1,
+            _ => return Err(CommandError::Edit(EditError::NoJob)),
+        };
+        let message = format!("{:?}", edit);
+        self.state
+            .edit_program(edit, current, first_editable)
+            .map_err(CommandError::Edit)?;
+        self.refresh();
+        // A repeat must not point past a shortened program
+        if self.last_completed.is_some_and(|last| last.index >= self.targets.len()) {
+            self.last_completed = None;
+        }
+        self.state.log_event(EventKind::ProgramEdited, message, self.state.uptime_ms());
+        Ok(())
+    }
+
+    fn set_teach_mode(&mut self, enabled: bool) -> CommandResult {
+        if enabled && !self.teach_mode {
+            let machine_state = self.state.get_machine_state();
+            if !matches!(machine_state, MachineState::Idle | MachineState::Manual) {
+                return Err(CommandError::Teach(TeachError::Busy(machine_state)));
+            }
+            self.state.clear_targets();
+            self.teach_peaks.reset();
+            info!("📐 Teach mode enabled - target list cleared");
+        } else if !enabled && self.teach_mode {
+            info!("📐 Teach mode disabled");
+        }
+        self.teach_mode = enabled;
+        Ok(())
+    }
+
+    /// Append the last stroke peak or the current angle to the target list.
+    fn teach(&mut self, source: TeachSource) -> CommandResult {
+        if !self.teach_mode {
+            return Err(CommandError::Teach(TeachError::NotInTeachMode));
+        }
+        let angle = match source {
+            TeachSource::Peak => self.teach_peaks.last_peak().ok_or(CommandError::Teach(TeachError::NoPeak))?,
+            TeachSource::Current => self.state.get_angle(),
+        };
+        let count = self.state.append_target(angle).map_err(|e| CommandError::Teach(TeachError::OutOfTravel(e)))?;
+        self.state.log_event(
+            EventKind::Taught,
+            format!("Taught target {}: {:.1}°", count, angle),
+            self.state.uptime_ms(),
+        );
+        Ok(())
+    }
+
+    /// Take the operator's measurement of the last completed bend and correct
+    /// that bend's compensation in the active program.
+    fn feedback(&mut self, measured_angle: f32) -> Result<(), FeedbackError> {
+        if !measured_angle.is_finite() {
+            return Err(FeedbackError::InvalidAngle);
+        }
+        let result = self.state.get_last_result().ok_or(FeedbackError::NoBend)?;
+        // An insert or delete since the bend was made would shift its index
+        if result.program_version != self.state.program_version() {
+            return Err(FeedbackError::ProgramChanged { bend: result.bend });
+        }
+        let compensation = feedback::corrected_compensation(result.compensation, result.target_angle, measured_angle);
+        let program_version = self.state.set_compensation(result.bend - 1, compensation)?;
+        self.state.set_last_result_program_version(program_version);
+        self.refresh();
+
+        let record = FeedbackRecord {
+            timestamp_ms: self.state.uptime_ms(),
+            run: result.run,
+            bend: result.bend,
+            target_angle: result.target_angle,
+            measured_angle,
+            deviation: measured_angle - result.target_angle,
+            previous_compensation: result.compensation,
+            compensation,
+        };
+        self.state.push_feedback(record);
+        self.state.log_event(
+            EventKind::Feedback,
+            format!("Bend {} measured {:.1}° (target {:.1}°): compensation {:+.1}° -> {:+.1}°",
+                record.bend, measured_angle, record.target_angle, record.previous_compensation, compensation),
+            record.timestamp_ms,
+        );
+        Ok(())
+    }
+
+    /// Two-point calibration. The conversion only changes with no job running,
+    /// so targets already converted to steps keep their meaning.
+    fn calibrate(&mut self, step: CalibrationCommand) -> Result<(), CalibrationError> {
+        let machine_state = self.state.get_machine_state();
+        if !matches!(machine_state, MachineState::Idle | MachineState::Manual) {
+            return Err(CalibrationError::Busy(machine_state));
+        }
+        match step {
+            CalibrationCommand::Capture { point, angle } => {
+                let slot = point.checked_sub(1)
+                    .and_then(|i| self.calibration_points.get_mut(i))
+                    .ok_or(CalibrationError::InvalidPoint(point))?;
+                let steps = self.state.get_value();
+                *slot = Some(CalibrationPoint { angle, steps });
+                info!("📏 Calibration point {}: {:.1}° at {} steps", point, angle, steps);
+            }
+            CalibrationCommand::Apply => {
+                let [Some(a), Some(b)] = self.calibration_points else {
+                    let missing = if self.calibration_points[0].is_none() { 1 } else { 2 };
+                    return Err(CalibrationError::MissingPoint(missing));
+                };
+                let calibration = Calibration::from_points(a, b)?;
+                self.store_calibration(Some(calibration));
+                self.calibration_points = [None; 2];
+                self.state.log_event(
+                    EventKind::Calibrated,
+                    format!("Calibrated: {:.4}°/step, offset {:.2}°", calibration.degrees_per_step, calibration.offset),
+                    self.state.uptime_ms(),
+                );
+            }
+            CalibrationCommand::Clear => {
+                self.store_calibration(None);
+                self.calibration_points = [None; 2];
+                info!("📏 Calibration cleared - using encoder resolution");
+            }
+        }
+        Ok(())
+    }
+
+    fn store_calibration(&mut self, calibration: Option<Calibration>) {
+        let mut settings = self.state.get_settings();
+        settings.calibration = calibration;
+        self.state.set_settings(settings);
+        self.refresh();
+    }
+
+    fn log_paused(&self, now_ms: u64) {
+        self.state.log_event(
+            EventKind::Paused,
+            format!("Paused before bend {}, run {}",
+                self.state.get_current_target_index() + 1, self.state.get_current_run()),
+            now_ms,
+        );
+    }
+
+    /// Run one control iteration and return the level the output pin must be driven to.
+    pub fn step(&mut self, inputs: ControlInputs, now_ms: u64) -> bool {
+        self.refresh();
+        let output_on = self.run(inputs, now_ms);
+        self.latency.update(output_on, self.motion, now_ms);
+        self.publish(now_ms);
+        output_on
+    }
+
+    /// Pick up settings and program changes. Only locks when a version changed.
+    fn refresh(&mut self) {
+        let settings_version = self.state.settings_version();
+        if settings_version != self.settings_version {
+            self.settings = self.state.get_settings();
+            self.settings_version = settings_version;
+            self.step_range = self.settings.step_range();
+        }
+        let program_version = self.state.program_version();
+        if program_version != self.program_version {
+            self.state.copy_program(&mut self.targets, &mut self.bend_options);
+            self.program_version = program_version;
+        }
+    }
+
+    fn publish(&mut self, now_ms: u64) {
+        let steps = self.state.get_value();
+        self.snapshot = ControlSnapshot {
+            sequence: self.snapshot.sequence.wrapping_add(1),
+            timestamp_ms: now_ms,
+            machine_state: self.state.get_machine_state(),
+            pause_pending: self.pause_requested,
+            steps,
+            angle: self.angle(steps),
+            degrees_per_second: self.degrees_per_second,
+            lead_angle: self.lead_angle,
+            current_target_index: self.state.get_current_target_index(),
+            output_on: self.state.is_output_on(),
+            current_run: self.state.get_current_run(),
+            total_runs: self.state.get_total_runs(),
+            interlock_open: self.guard_door_open || self.light_curtain_open,
+            stalled: self.state.is_stalled(),
+            idle_ms: self.idle_ms,
+            teach_mode: self.teach_mode,
+            teach_peak: self.teach_peaks.last_peak(),
+            calibration_points: self.calibration_points,
+            latency: self.latency.stats(),
+            settings_version: self.settings_version,
+        };
+        self.state.snapshot.publish(&self.snapshot);
+    }
+
+    fn angle(&self, steps: i32) -> f32 {
+        self.settings.steps_to_degrees(steps)
+    }
+
+    fn run(&mut self, inputs: ControlInputs, now_ms: u64) -> bool {
+        // A pending pause dies with the job (Stop, fault, last bend done)
+        let machine_state = self.state.get_machine_state();
+        if !machine_state.is_running() {
+            self.pause_requested = false;
+            if machine_state != MachineState::Paused {
+                self.last_completed = None;
+            }
+        }
+        // Teach mode ends when the machine leaves manual operation (fault, stop, job)
+        if self.teach_mode && !matches!(machine_state, MachineState::Idle | MachineState::Manual) {
+            self.teach_mode = false;
+            info!("📐 Teach mode ended by {:?}", machine_state);
+        }
+
+        self.motion = self.settings.forward_direction.apply(inputs.direction);
+        self.state.move_by(inputs.direction, self.settings.forward_direction, self.step_range);
+
+        // The reference marks the top of every stroke: re-zero there so missed
+        // steps do not add up over a job
+        let reference_active = inputs.reference_active && self.settings.reference_input_enabled;
+        if reference_active && !self.reference_active {
+            let drift = self.state.get_value();
+            self.state.set_zero();
+            let drift = drift - self.state.get_value();
+            if drift != 0 {
+                info!("📍 Reference re-zeroed the count ({} steps drift)", drift);
+            }
+        }
+        self.reference_active = reference_active;
+
+        // A count pinned at the end of the range means missed steps or a wrong encoder resolution
+        let value = self.state.get_value();
+        if self.step_range.is_saturated(value, self.motion) {
+            self.state.raise_alarm(AlarmCode::EncoderError, "Encoder count saturated at travel limit", now_ms);
+            self.encoder_error_raised = true;
+        } else if self.encoder_error_raised && !self.step_range.is_at_limit(value) {
+            self.state.clear_alarm_condition(AlarmCode::EncoderError, now_ms);
+            self.encoder_error_raised = false;
+        }
+
+        // A panic elsewhere left shared data possibly half-updated: hold the
+        // output off until the controller is restarted
+        if self.state.is_lock_poisoned() && !self.internal_error_raised {
+            self.internal_error_raised = true;
+            self.state.raise_alarm(
+                AlarmCode::InternalError,
+                "Shared state recovered after a panic - restart required",
+                now_ms,
+            );
+        }
+
+        let threshold = self.settings.minimum_angle_threshold;
+        let angle = self.angle(self.state.get_value());
+        self.velocity.update(angle, now_ms);
+        self.degrees_per_second = self.velocity.degrees_per_second(now_ms);
+        self.lead_angle = 0.0;
+
+        if self.teach_mode {
+            if let Some(peak) = self.teach_peaks.update(angle, self.settings.hysteresis.rearm_angle(threshold), threshold) {
+                info!("📐 Stroke peak: {:.1}°", peak);
+            }
+        }
+
+        // Keep following the peak of an overtravel until the ram is back at the top,
+        // even if the overtravel started a safe stop
+        if self.overtravel_raised {
+            if angle < threshold {
+                self.overtravel_raised = false;
+                self.state.clear_alarm_condition(AlarmCode::Overtravel, now_ms);
+            } else if self.state.get_value() > self.peak_steps {
+                self.peak_steps = self.state.get_value();
+                if let Some(mut record) = self.state.get_last_overtravel() {
+                    record.peak_angle = angle;
+                    self.state.set_last_overtravel(record);
+                }
+            }
+        }
+
+        self.update_interlocks(inputs, now_ms);
+
+        // Safe stop ended by something other than this loop (abort, Start, fault)
+        if self.safe_stop_started_ms.is_some() && !self.state.is_safe_stop_active() {
+            self.finish_safe_stop(SafeStopOutcome::Cancelled, now_ms);
+        }
+
+        // Blocking alarms override everything, including safe stop and manual output
+        if self.state.get_machine_state() == MachineState::Fault {
+            return self.set_output(false);
+        }
+
+        if self.state.is_active() && !self.was_active {
+            self.rearm_pending = false;
+            self.toggle_on = false;
+            self.stroke_started_ms = None;
+        }
+        self.monitor_motion(inputs.direction, angle, now_ms);
+
+        // Handle target angle logic - safe stop has the highest priority
+        let machine_state = self.state.get_machine_state();
+        if machine_state == MachineState::SafeStop {
+            let started_ms = *self.safe_stop_started_ms.get_or_insert(now_ms);
+            let elapsed_ms = now_ms.saturating_sub(started_ms);
+            let timeout_ms = self.settings.safe_stop_timeout_ms;
+            if angle < threshold {
+                // Angle has dropped below the minimum threshold - safe stop complete
+                self.state.complete_safe_stop();
+                info!("✅ Safe stop complete - output OFF, angle reset to 0°");
+                self.finish_safe_stop(SafeStopOutcome::Completed, now_ms);
+                self.set_output(false)
+            } else if timeout_ms > 0 && elapsed_ms >= timeout_ms as u64 {
+                // The ram never came back - give up, turn the output off and latch a fault
+                self.finish_safe_stop(SafeStopOutcome::TimedOut, now_ms);
+                self.state.raise_alarm(
+                    AlarmCode::SafeStopTimeout,
+                    format!("Safe stop did not return below {:.1}° within {} ms (at {:.1}°)",
+                        threshold, elapsed_ms, angle),
+                    now_ms,
+                );
+                self.set_output(false)
+            } else {
+                // Keep output ON to trigger machine reversal
+                self.set_output(true)
+            }
+        } else if machine_state.is_running() {
+            self.step_active(machine_state, now_ms)
+        } else if machine_state == MachineState::Manual {
+            self.step_manual(now_ms)
+        } else {
+            // Encoder not active and no manual override - ensure output pin is off
+            self.set_output(false)
+        }
+    }
+
+    /// Manual output override: hold-to-run with a keep-alive timeout and a
+    /// limit on the continuous on-time.
+    fn step_manual(&mut self, now_ms: u64) -> bool {
+        if std::mem::take(&mut self.manual_renewed) {
+            self.manual_keepalive_ms = now_ms;
+        }
+        let on = self.state.get_manual_output_state();
+        if !on {
+            self.manual_on_since_ms = None;
+        } else if self.manual_on_since_ms.is_none() {
+            self.manual_on_since_ms = Some(now_ms);
+        }
+
+        let keepalive_timeout_ms = self.settings.manual_keepalive_timeout_ms as u64;
+        let max_on_ms = self.settings.manual_max_on_ms as u64;
+        let expired = if keepalive_timeout_ms > 0 && now_ms.saturating_sub(self.manual_keepalive_ms) >= keepalive_timeout_ms {
+            Some(format!("Manual output override cleared - no keep-alive for {} ms", keepalive_timeout_ms))
+        } else {
+            self.manual_on_since_ms
+                .filter(|&since| max_on_ms > 0 && now_ms.saturating_sub(since) >= max_on_ms)
+                .map(|_| format!("Manual output switched off after the {} ms on-time limit", max_on_ms))
+        };
+        match expired {
+            Some(message) => {
+                self.state.clear_manual_output();
+                self.manual_on_since_ms = None;
+                if on {
+                    warn!("⏱️ {}", message);
+                    self.state.log_event(EventKind::ManualExpired, message, now_ms);
+                }
+                self.set_output(false)
+            }
+            None => self.set_output(on),
+        }
+    }
+
+    /// Feed the interlock inputs to the monitor and alarm manager. Only
+    /// changes are passed on, so a steady input costs no locking.
+    fn update_interlocks(&mut self, inputs: ControlInputs, now_ms: u64) {
+        let guard_door_open = inputs.guard_door_open && self.settings.guard_door_interlock_enabled;
+        let light_curtain_open = inputs.light_curtain_open && self.settings.light_curtain_interlock_enabled;
+        let was_open = self.guard_door_open || self.light_curtain_open;
+
+        if guard_door_open != self.guard_door_open {
+            self.guard_door_open = guard_door_open;
+            if self.state.update_interlock(InterlockInput::GuardDoor, guard_door_open, now_ms) {
+                self.state.raise_alarm(AlarmCode::Interlock, "Guard door open", now_ms);
+            }
+        }
+        if light_curtain_open != self.light_curtain_open {
+            self.light_curtain_open = light_curtain_open;
+            if self.state.update_interlock(InterlockInput::LightCurtain, light_curtain_open, now_ms) {
+                self.state.raise_alarm(AlarmCode::Interlock, "Light curtain interrupted", now_ms);
+            }
+        }
+        if was_open && !(guard_door_open || light_curtain_open) {
+            self.state.clear_alarm_condition(AlarmCode::Interlock, now_ms);
+        }
+    }
+
+    fn step_active(&mut self, machine_state: MachineState, now_ms: u64) -> bool {
+        let current_idx = self.state.get_current_target_index();
+        let Some(&target) = self.targets.get(current_idx) else {
+            return self.state.is_output_on();
+        };
+
+        let steps = self.state.get_value();
+        let angle = self.angle(steps);
+        // The ram is driven past the target by the springback compensation
+        let target_angle = self.angle(target) + self.compensation(current_idx);
+        let thresholds = self.hysteresis(current_idx).thresholds(target_angle, self.settings.minimum_angle_threshold);
+        // Fire early by the distance the ram covers while the valve responds
+        self.lead_angle = velocity::lead_angle(self.degrees_per_second, self.settings.output_latency_ms);
+        if matches!(machine_state, MachineState::Armed | MachineState::Bending) && angle >= thresholds.reset {
+            self.stroke_started_ms.get_or_insert(now_ms);
+        }
+
+        let output_on = match machine_state {
+            // Trigger output when reaching target (moving forward from 0)
+            MachineState::Armed | MachineState::Bending if angle + self.lead_angle >= thresholds.trigger => {
+                if !self.state.transition_from(machine_state, MachineState::Returning) {
+                    // Stopped from the web server in the meantime
+                    return self.state.is_output_on();
+                }
+                self.peak_steps = steps;
+                self.overtravel_raised = false;
+                let stroke_ms = now_ms.saturating_sub(self.stroke_started_ms.take().unwrap_or(now_ms));
+                self.time_to_target_ms = stroke_ms.min(u32::MAX as u64) as u32;
+                if self.lead_angle > 0.0 {
+                    info!("⚡ Target {:.1}°: output fired at {:.1}° ({:.1}° lead at {:.0}°/s)",
+                        target_angle, angle, self.lead_angle, self.degrees_per_second);
+                } else {
+                    info!("⚡ Target reached: {:.1}°", target_angle);
+                }
+                self.fire(current_idx, &thresholds, now_ms)
+            }
+            // Target was reached, now manage output based on the output mode
+            MachineState::Returning => self.returning_output(angle, &thresholds, now_ms),
+            // Toggle bends keep their level until the next trigger
+            _ => self.toggle_on,
+        };
+        self.set_output(output_on);
+
+        let returning = self.state.get_machine_state() == MachineState::Returning;
+        if returning && !self.overtravel_raised {
+            self.check_overtravel(current_idx, steps, target_angle, now_ms);
+        }
+
+        // Reset encoder if angle drops below threshold AND target was already triggered
+        if returning && angle < thresholds.reset && !self.rearm_pending {
+            // With a reference input the count is re-zeroed at the top instead
+            if !self.settings.reference_input_enabled {
+                self.state.set_zero();
+                info!("🔄 Encoder reset to zero");
+            }
+            self.rearm_pending = true;
+            self.record_result(current_idx, now_ms);
+
+            self.last_completed = Some(StepPosition {
+                index: current_idx,
+                run: self.state.get_current_run(),
+            });
+
+            // Advance to next target
+            let new_idx = current_idx + 1;
+            self.state.set_current_target_index(new_idx);
+
+            // Ram is back at the top: a requested pause takes effect here
+            let next_state = if self.pause_requested {
+                MachineState::Paused
+            } else {
+                MachineState::Armed
+            };
+            if new_idx < self.targets.len() {
+                self.state.transition_from(MachineState::Returning, next_state);
+            } else {
+                // All targets for this run completed
+                let current_run = self.state.get_current_run();
+                let total_runs = self.state.get_total_runs();
+                info!("✅ Run {}/{} completed and returned to 0°.", current_run, total_runs);
+
+                if current_run < total_runs {
+                    // Start next run
+                    self.state.increment_current_run();
+                    self.state.set_current_target_index(0);
+                    self.state.transition_from(MachineState::Returning, next_state);
+                    info!("🔄 Starting run {}/{}...", self.state.get_current_run(), total_runs);
+                } else {
+                    // All runs completed - initiate safe stop
+                    info!("✅ All {} runs completed!", total_runs);
+                    self.state.stop();
+                }
+            }
+        }
+
+        if self.pause_requested && self.state.get_machine_state() == MachineState::Paused {
+            self.pause_requested = false;
+            self.log_paused(now_ms);
+        }
+
+        // Re-arm on the thresholds of the bend now waiting, which changed at a reset
+        let rearm = self.hysteresis(self.state.get_current_target_index()).rearm_angle(thresholds.reset);
+        if angle > rearm {
+            self.rearm_pending = false;
+            self.state.transition_from(MachineState::Armed, MachineState::Bending);
+        }
+
+        self.state.is_output_on()
+    }
+
+    fn finish_safe_stop(&mut self, outcome: SafeStopOutcome, now_ms: u64) {
+        let Some(started_ms) = self.safe_stop_started_ms.take() else {
+            return;
+        };
+        let duration_ms = now_ms.saturating_sub(started_ms).min(u32::MAX as u64) as u32;
+        self.state.set_last_safe_stop(SafeStopReport { outcome, duration_ms });
+        match outcome {
+            SafeStopOutcome::Completed => self.state.log_event(
+                EventKind::SafeStopCompleted,
+                format!("Safe stop completed in {} ms", duration_ms),
+                now_ms,
+            ),
+            SafeStopOutcome::TimedOut => self.state.log_event(
+                EventKind::SafeStopTimeout,
+                format!("Safe stop timed out after {} ms", duration_ms),
+                now_ms,
+            ),
+            SafeStopOutcome::Cancelled => info!("Safe stop cancelled after {} ms", duration_ms),
+        }
+    }
+
+    /// Stall and idle supervision while a job is running. A stall is only
+    /// reported mid-bend; waiting at the top of the stroke is normal.
+    fn monitor_motion(&mut self, direction: i32, angle: f32, now_ms: u64) {
+        let active = self.state.is_active();
+        if direction != 0 || !active || !self.was_active {
+            if self.state.is_stalled() {
+                self.state.stalled.store(false, Ordering::SeqCst);
+                self.state.clear_alarm_condition(AlarmCode::Stall, now_ms);
+                if direction != 0 {
+                    self.state.log_event(EventKind::MotionResumed, "Encoder movement resumed", now_ms);
+                }
+            }
+            self.last_motion_ms = now_ms;
+        }
+        self.was_active = active;
+
+        let idle_ms = now_ms.saturating_sub(self.last_motion_ms);
+        self.idle_ms = idle_ms.min(u32::MAX as u64) as u32;
+        if !active {
+            return;
+        }
+
+        let settings = &self.settings;
+        if settings.stall_timeout_ms > 0
+            && idle_ms >= settings.stall_timeout_ms as u64
+            && !self.state.is_stalled()
+            && angle >= settings.minimum_angle_threshold {
+            self.state.stalled.store(true, Ordering::SeqCst);
+            let message = format!("No encoder movement for {} ms at {:.1}°", idle_ms, angle);
+            self.state.raise_alarm(AlarmCode::Stall, message.clone(), now_ms);
+            self.state.log_event(EventKind::Stall, message, now_ms);
+        }
+
+        if settings.idle_timeout_ms > 0 && idle_ms >= settings.idle_timeout_ms as u64 {
+            self.state.log_event(
+                EventKind::IdleTimeout,
+                format!("Job ended after {} ms without encoder movement", idle_ms),
+                now_ms,
+            );
+            self.state.stop();
+        }
+    }
+
+    /// Start the output sequence of a bend at its trigger point. Returns the
+    /// output level for this iteration.
+    fn fire(&mut self, bend_index: usize, thresholds: &Thresholds, now_ms: u64) -> bool {
+        self.output_mode = self.bend_options.get(bend_index)
+            .and_then(|options| options.output_mode)
+            .unwrap_or(self.settings.output_mode);
+        self.fired_ms = now_ms;
+        self.released = false;
+        self.release_angle = None;
+        match self.output_mode {
+            OutputMode::Level => {
+                // The release point moves down with the lead the output fired with
+                self.release_angle = Some(thresholds.release - self.lead_angle);
+                self.toggle_on = false;
+                true
+            }
+            OutputMode::Pulse { .. } => {
+                self.toggle_on = false;
+                true
+            }
+            OutputMode::Dwell { dwell_ms } => {
+                self.toggle_on = false;
+                info!("⏳ Dwelling {} ms at target", dwell_ms);
+                false
+            }
+            OutputMode::Toggle => {
+                self.toggle_on = !self.toggle_on;
+                self.toggle_on
+            }
+        }
+    }
+
+    /// Output level after the trigger point, by the output mode of the bend.
+    /// Once the output has switched off it stays off for the rest of the stroke.
+    fn returning_output(&mut self, angle: f32, thresholds: &Thresholds, now_ms: u64) -> bool {
+        if self.output_mode == OutputMode::Toggle {
+            return self.toggle_on;
+        }
+        if self.released {
+            return false;
+        }
+        let elapsed_ms = now_ms.saturating_sub(self.fired_ms);
+        let on = match self.output_mode {
+            OutputMode::Pulse { width_ms } => elapsed_ms < width_ms as u64,
+            OutputMode::Dwell { dwell_ms } if elapsed_ms < dwell_ms as u64 => return false,
+            _ => {
+                // Dwell releases relative to where the ram was when the dwell ended
+                let release_band = thresholds.trigger - thresholds.release;
+                let release_angle = *self.release_angle.get_or_insert(angle - release_band);
+                if self.settings.hold_output_until_threshold {
+                    // Keep output on until angle drops below threshold
+                    angle >= thresholds.reset
+                } else {
+                    // Turn off output once the ram is back past the release band
+                    angle >= release_angle
+                }
+            }
+        };
+        self.released = !on;
+        on
+    }
+
+    /// Springback compensation of a bend in degrees
+    fn compensation(&self, bend_index: usize) -> f32 {
+        self.bend_options.get(bend_index).map_or(0.0, |options| options.compensation)
+    }
+
+    /// Hysteresis of a bend, with its overrides applied
+    fn hysteresis(&self, bend_index: usize) -> Hysteresis {
+        match self.bend_options.get(bend_index) {
+            Some(options) => self.settings.hysteresis.with_override(&options.hysteresis),
+            None => self.settings.hysteresis,
+        }
+    }
+
+    /// Store the peak and timing of a bend whose stroke just ended and check
+    /// the peak against the bend's accept tolerance.
+    fn record_result(&mut self, bend_index: usize, now_ms: u64) {
+        let return_ms = now_ms.saturating_sub(self.fired_ms).min(u32::MAX as u64) as u32;
+        let target_angle = self.targets.get(bend_index).map_or(0.0, |&target| self.angle(target));
+        let compensation = self.compensation(bend_index);
+        let peak_angle = self.angle(self.peak_steps);
+        let tolerance = self.bend_options.get(bend_index)
+            .and_then(|options| options.accept_tolerance)
+            .unwrap_or(self.settings.accept_tolerance);
+        let result = BendResult {
+            run: self.state.get_current_run(),
+            bend: bend_index + 1,
+            target_angle,
+            compensation,
+            peak_angle,
+            time_to_target_ms: self.time_to_target_ms,
+            return_time_ms: return_ms,
+            rejected: tolerance > 0.0 && (peak_angle - (target_angle + compensation)).abs() > tolerance,
+            program_version: self.program_version,
+        };
+        info!("📊 Bend {}: peak {:.1}° (target {:.1}°), {} ms to target, {} ms return",
+            result.bend, peak_angle, target_angle, result.time_to_target_ms, result.return_time_ms);
+        self.state.push_result(result);
+        if !result.rejected {
+            return;
+        }
+
+        let message = format!(
+            "Bend {} of run {} reached {:.1}°, outside {:.1}° ± {:.1}°",
+            result.bend, result.run, peak_angle, target_angle + compensation, tolerance
+        );
+        warn!("❌ {}", message);
+        if self.settings.reject_alarm {
+            // Latched for the operator to see; there is no condition to wait for
+            self.state.raise_alarm(AlarmCode::Reject, message, now_ms);
+            self.state.clear_alarm_condition(AlarmCode::Reject, now_ms);
+        }
+        if self.settings.reject_pause && !self.pause_requested {
+            info!("⏸️ Rejected bend - pausing at the top");
+            self.pause_requested = true;
+        }
+    }
+
+    /// Raise an overtravel alarm once the ram has gone further past the target
+    /// than the bend's tolerance allows.
+    fn check_overtravel(
+        &mut self,
+        bend_index: usize,
+        steps: i32,
+        target_angle: f32,
+        now_ms: u64,
+    ) {
+        self.peak_steps = self.peak_steps.max(steps);
+        let tolerance = self.bend_options.get(bend_index)
+            .and_then(|options| options.overtravel_tolerance)
+            .unwrap_or(self.settings.overtravel_tolerance);
+        let peak_angle = self.angle(self.peak_steps);
+        if peak_angle <= target_angle + tolerance {
+            return;
+        }
+
+        self.overtravel_raised = true;
+        self.state.set_last_overtravel(OvertravelRecord {
+            run: self.state.get_current_run(),
+            bend_index,
+            target_angle,
+            peak_angle,
+        });
+        self.state.raise_alarm(
+            AlarmCode::Overtravel,
+            format!(
+                "Bend {} passed target {:.1}° by more than {:.1}°",
+                bend_index + 1,
+                target_angle,
+                tolerance
+            ),
+            now_ms,
+        );
+        if self.settings.overtravel_safe_stop {
+            info!("🛑 Overtravel - initiating safe stop");
+            self.state.stop();
+        }
+    }
+
+    fn set_output(&self, on: bool) -> bool {
+        self.state.output_on.store(on, Ordering::SeqCst);
+        on
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmError;
+    use crate::rotary::{BendOptions, ForwardDirection, StartError, StepMode};
+    use crate::hysteresis::HysteresisOverride;
+    use crate::resolution::EncoderResolution;
+
+    fn make_controller() -> (Controller, RotaryEncoderState) {
+        let state = RotaryEncoderState::new();
+        let settings = Settings {
+            guard_door_interlock_enabled: true,
+            light_curtain_interlock_enabled: true,
+            ..Settings::default()
+        };
+        state.set_settings(settings);
+        (Controller::new(state.clone()), state)
+    }
+
+    fn moving(direction: i32) -> ControlInputs {
+        ControlInputs { direction, ..Default::default() }
+    }
+
+    fn guard_open() -> ControlInputs {
+        ControlInputs { guard_door_open: true, ..Default::default() }
+    }
+
+    /// Step the controller forward until the encoder reaches `steps`.
+    fn drive_to(controller: &mut Controller, state: &RotaryEncoderState, steps: i32) -> bool {
+        let mut output = false;
+        while state.get_value() < steps {
+            output = controller.step(moving(1), 0);
+        }
+        output
+    }
+
+    #[test]
+    fn output_turns_on_at_target() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(!drive_to(&mut controller, &state, 9));
+        assert!(drive_to(&mut controller, &state, 10));
+        assert_eq!(state.get_machine_state(), MachineState::Returning);
+    }
+
+    #[test]
+    fn return_below_threshold_advances_to_next_target() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        while state.get_value() > 0 {
+            controller.step(moving(-1), 0);
+        }
+        controller.step(moving(0), 0);
+        assert_eq!(state.get_current_target_index(), 1);
+        assert!(!state.is_output_on());
+    }
+
+    #[test]
+    fn stroke_walks_through_machine_states() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(state.get_machine_state(), MachineState::Bending);
+        drive_to(&mut controller, &state, 10);
+        assert_eq!(state.get_machine_state(), MachineState::Returning);
+        while state.get_value() > 0 {
+            controller.step(moving(-1), 0);
+        }
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+    }
+
+    #[test]
+    fn last_bend_ends_in_safe_stop_then_idle() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        while state.get_value() > 0 {
+            controller.step(moving(-1), 0);
+        }
+        assert_eq!(state.get_machine_state(), MachineState::SafeStop);
+        controller.step(moving(0), 0);
+        assert_eq!(state.get_machine_state(), MachineState::Idle);
+    }
+
+    #[test]
+    fn manual_output_rejected_while_running() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        assert!(state.set_manual_output(false).is_err());
+        assert!(controller.step(moving(0), 0), "Running job keeps control of the output");
+    }
+
+    #[test]
+    fn each_step_publishes_a_snapshot() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        let snapshot = state.get_snapshot();
+        assert_eq!(snapshot.sequence, 10);
+        assert_eq!(snapshot.machine_state, MachineState::Returning);
+        assert_eq!(snapshot.steps, 10);
+        assert!(snapshot.output_on);
+    }
+
+    #[test]
+    fn settings_update_is_picked_up_by_version() {
+        let (mut controller, state) = make_controller();
+        controller.step(moving(0), 0);
+        let version = state.get_snapshot().settings_version;
+        let mut settings = state.get_settings();
+        settings.resolution = EncoderResolution::from(StepMode::Half);
+        state.set_settings(settings);
+        state.set_value(90);
+        controller.step(moving(0), 1);
+        let snapshot = state.get_snapshot();
+        assert_eq!(snapshot.settings_version, version + 1);
+        assert!((snapshot.angle - 45.0).abs() < 1e-6);
+    }
+
+    /// Host benchmark of the control loop; see "Control Loop Benchmark" in
+    /// TESTING.md for the host build it runs in.
+    #[test]
+    #[ignore]
+    fn bench_step() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.number_of_runs = 1_000_000;
+        settings.stall_timeout_ms = 0;
+        state.set_settings(settings);
+        state.set_program(vec![45.0, 90.0, 135.0], Vec::new()).unwrap();
+
+        // One step every 4 ms (~250°/s at 1 kHz polling); the ram reverses when
+        // the output fires and comes back down to 0
+        const ITERATIONS: u64 = 1_000_000;
+        let mut direction = 1;
+        let start = std::time::Instant::now();
+        for i in 0..ITERATIONS {
+            let moving_now = if i % 4 == 0 { direction } else { 0 };
+            if controller.step(moving(moving_now), i) {
+                direction = -1;
+            } else if state.get_value() == 0 {
+                direction = 1;
+            }
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "control step: {:.0} ns/iteration over {} iterations",
+            elapsed.as_nanos() as f64 / ITERATIONS as f64,
+            ITERATIONS
+        );
+        assert!(state.get_current_run() > 1, "Benchmark must cycle through complete runs");
+    }
+
+    /// Drive the ram down to 0 and let the controller see the reset.
+    fn return_to_top(controller: &mut Controller, state: &RotaryEncoderState) {
+        while state.get_value() > 0 {
+            controller.step(moving(-1), 0);
+        }
+        controller.step(moving(0), 0);
+    }
+
+    /// Feed encoder movements one per iteration and collect the output levels.
+    fn chatter(controller: &mut Controller, directions: &[i32]) -> Vec<bool> {
+        directions.iter().map(|&direction| controller.step(moving(direction), 0)).collect()
+    }
+
+    #[test]
+    fn chatter_at_target_does_not_release_output() {
+        let (mut controller, state) = make_controller();
+        let hysteresis = Hysteresis { release_band: 1.0, ..Hysteresis::default() };
+        state.set_settings(Settings { hysteresis, ..state.get_settings() });
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        // 9..10°, inside the 1° release band
+        assert_eq!(chatter(&mut controller, &[-1, 1, -1, 1, -1]), vec![true; 5]);
+        assert_eq!(chatter(&mut controller, &[-1]), vec![false], "Released below 9°");
+        assert_eq!(chatter(&mut controller, &[1, 1, 1]), vec![false; 3], "No second trigger in the same stroke");
+    }
+
+    #[test]
+    fn chatter_at_threshold_ends_stroke_once() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0, 30.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        while state.get_value() > 2 {
+            controller.step(moving(-1), 0);
+        }
+        assert_eq!(state.get_current_target_index(), 1);
+        // Up and down across the 2.5° threshold, below the 5° re-arm angle
+        chatter(&mut controller, &[1, -1, 1, 1, -1, -1, 1, 1, 1, -1, -1, -1]);
+        assert_eq!(state.get_current_target_index(), 1, "Bend must only advance once");
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(state.get_machine_state(), MachineState::Bending, "Re-armed above 5°");
+    }
+
+    #[test]
+    fn hysteresis_is_applied_per_bend() {
+        let (mut controller, state) = make_controller();
+        let second = HysteresisOverride { trigger_offset: Some(-2.0), rearm_band: Some(7.5), ..Default::default() };
+        let options = vec![BendOptions::default(), BendOptions { hysteresis: second, ..Default::default() }];
+        state.set_program(vec![10.0, 20.0], options).unwrap();
+        drive_to(&mut controller, &state, 10);
+        return_to_top(&mut controller, &state);
+        drive_to(&mut controller, &state, 9);
+        assert_eq!(state.get_machine_state(), MachineState::Armed, "Second bend re-arms at 10°");
+        drive_to(&mut controller, &state, 11);
+        assert_eq!(state.get_machine_state(), MachineState::Bending);
+        assert!(!drive_to(&mut controller, &state, 17));
+        assert!(drive_to(&mut controller, &state, 18), "Second bend fires 2° early");
+    }
+
+    /// Move one count every `gap_ms` up to `steps`, with real timestamps.
+    /// Returns the angle the output fired at, if it did.
+    fn drive_timed(controller: &mut Controller, state: &RotaryEncoderState, steps: i32, gap_ms: u64, now_ms: &mut u64) -> Option<f32> {
+        let mut fired_at = None;
+        while state.get_value() < steps {
+            for i in 0..gap_ms {
+                *now_ms += 1;
+                let direction = if i == 0 { 1 } else { 0 };
+                if controller.step(moving(direction), *now_ms) && fired_at.is_none() {
+                    fired_at = Some(state.get_angle());
+                }
+            }
+        }
+        fired_at
+    }
+
+    #[test]
+    fn output_fires_early_by_latency_at_speed() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.output_latency_ms = 20;
+        state.set_settings(settings);
+        state.set_program(vec![30.0], Vec::new()).unwrap();
+        let mut now_ms = 0;
+        // 250°/s: 5° covered in 20 ms
+        assert_eq!(drive_timed(&mut controller, &state, 29, 4, &mut now_ms), Some(25.0));
+        let snapshot = state.get_snapshot();
+        assert_eq!(snapshot.degrees_per_second, 250.0);
+        assert_eq!(snapshot.lead_angle, 5.0);
+        assert!(snapshot.output_on, "Release point moves down with the lead");
+    }
+
+    #[test]
+    fn slow_stroke_fires_close_to_target() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.output_latency_ms = 20;
+        state.set_settings(settings);
+        state.set_program(vec![30.0], Vec::new()).unwrap();
+        let mut now_ms = 0;
+        // 10°/s: 0.2° lead rounds to the target count
+        assert_eq!(drive_timed(&mut controller, &state, 30, 100, &mut now_ms), Some(30.0));
+    }
+
+    #[test]
+    fn no_lead_without_latency_setting() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![30.0], Vec::new()).unwrap();
+        let mut now_ms = 0;
+        assert_eq!(drive_timed(&mut controller, &state, 30, 4, &mut now_ms), Some(30.0));
+        assert_eq!(state.get_snapshot().lead_angle, 0.0);
+        assert_eq!(state.get_snapshot().degrees_per_second, 250.0);
+    }
+
+    #[test]
+    fn output_to_reversal_latency_is_measured() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        let mut now_ms = 0;
+        drive_timed(&mut controller, &state, 10, 4, &mut now_ms);
+        let fired_ms = now_ms - 3;
+        controller.step(moving(0), fired_ms + 20);
+        controller.step(moving(-1), fired_ms + 42);
+        let latency = state.get_snapshot().latency;
+        assert_eq!(latency.samples, 1);
+        assert_eq!(latency.last_ms, Some(42));
+        assert_eq!(controller.apply(Command::ResetLatency), Ok(()));
+        controller.step(moving(0), fired_ms + 43);
+        assert_eq!(state.get_snapshot().latency.samples, 0);
+    }
+
+    #[test]
+    fn completed_bend_records_peak_and_timing() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        let mut now_ms = 0;
+        // Leaves the 2.5° threshold at 21 ms, reaches 10° at 91 ms and goes on to 12°
+        drive_timed(&mut controller, &state, 12, 10, &mut now_ms);
+        assert!(state.get_results().results.is_empty(), "Recorded once the ram is back at the top");
+        while state.get_value() > 0 {
+            now_ms += 5;
+            controller.step(moving(-1), now_ms);
+        }
+
+        let report = state.get_results();
+        assert_eq!(report.dropped, 0);
+        assert_eq!(report.results, vec![BendResult {
+            run: 1,
+            bend: 1,
+            target_angle: 10.0,
+            peak_angle: 12.0,
+            time_to_target_ms: 70,
+            return_time_ms: 79,
+            compensation: 0.0,
+            rejected: false,
+            program_version: state.program_version(),
+        }]);
+
+        state.stop();
+        controller.step(moving(0), now_ms + 1);
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(state.get_results().results.is_empty(), "Start clears the results");
+    }
+
+    /// Drive one stroke up to `peak` steps and back to the top
+    fn bend_to(controller: &mut Controller, state: &RotaryEncoderState, peak: i32) {
+        drive_to(controller, state, peak);
+        return_to_top(controller, state);
+    }
+
+    #[test]
+    fn bend_outside_accept_tolerance_is_rejected() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings { accept_tolerance: 1.0, ..state.get_settings() });
+        let bends = vec![BendOptions::default(), BendOptions { accept_tolerance: Some(3.0), ..Default::default() }];
+        state.set_program(vec![10.0, 20.0], bends).unwrap();
+        bend_to(&mut controller, &state, 12);
+        bend_to(&mut controller, &state, 22);
+
+        let report = state.get_results();
+        assert_eq!(report.results.iter().map(|r| r.rejected).collect::<Vec<_>>(), vec![true, false],
+            "The second bend's own tolerance accepts 2° over");
+        assert_eq!(report.rejects, 1);
+        assert_eq!(state.get_rejects().1.map(|r| r.bend), Some(1));
+        assert!(state.get_latched_alarms().is_empty(), "Alarm is off by default");
+    }
+
+    #[test]
+    fn reject_can_raise_alarm_and_pause() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings {
+            accept_tolerance: 1.0,
+            reject_alarm: true,
+            reject_pause: true,
+            ..state.get_settings()
+        });
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        bend_to(&mut controller, &state, 12);
+        assert_eq!(state.get_machine_state(), MachineState::Paused);
+        assert_eq!(state.get_current_target_index(), 1);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::Reject));
+        assert_eq!(state.acknowledge_alarm(AlarmCode::Reject), Ok(()));
+    }
+
+    #[test]
+    fn measured_springback_compensates_the_bend() {
+        let (mut controller, state) = make_controller();
+        assert_eq!(controller.apply(Command::Feedback(88.0)), Err(CommandError::Feedback(FeedbackError::NoBend)));
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        bend_to(&mut controller, &state, 10);
+
+        // Sprang back to 8°: a second measurement replaces the first correction
+        controller.apply(Command::Feedback(7.0)).unwrap();
+        assert_eq!(controller.apply(Command::Feedback(8.0)), Ok(()));
+        let history = state.get_feedback_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[1].deviation, history[1].compensation), (-2.0, 2.0));
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::Feedback);
+
+        controller.apply(Command::Step(StepCommand::Repeat)).unwrap();
+        assert!(!drive_to(&mut controller, &state, 11), "Target moved out to 12°");
+        assert!(drive_to(&mut controller, &state, 12));
+        return_to_top(&mut controller, &state);
+        let result = state.get_last_result().unwrap();
+        assert_eq!((result.bend, result.target_angle, result.compensation), (1, 10.0, 2.0));
+    }
+
+    #[test]
+    fn feedback_refused_after_program_edit() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings { number_of_runs: 2, ..state.get_settings() });
+        state.set_program(vec![10.0, 20.0, 30.0], Vec::new()).unwrap();
+        bend_to(&mut controller, &state, 10);
+        bend_to(&mut controller, &state, 20);
+        bend_to(&mut controller, &state, 30);
+        // Next run: bend 1 is editable again, inserting ahead of the measured bend 3
+        controller.apply(Command::EditProgram(ProgramEdit::Insert { bend: 1, angle: 5.0, options: BendOptions::default() })).unwrap();
+        assert_eq!(
+            controller.apply(Command::Feedback(28.0)),
+            Err(CommandError::Feedback(FeedbackError::ProgramChanged { bend: 3 }))
+        );
+        let mut targets = Vec::new();
+        let mut options = Vec::new();
+        state.copy_program(&mut targets, &mut options);
+        assert!(options.iter().all(|o| o.compensation == 0.0));
+        assert!(state.get_feedback_history().is_empty());
+    }
+
+    #[test]
+    fn compensation_is_kept_within_travel() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        bend_to(&mut controller, &state, 10);
+        assert!(matches!(
+            controller.apply(Command::Feedback(21.0)),
+            Err(CommandError::Feedback(FeedbackError::OutOfTravel(_)))
+        ), "-11° compensation would put the target below 0°");
+        assert!(state.get_feedback_history().is_empty());
+        assert_eq!(
+            controller.apply(Command::Feedback(f32::NAN)),
+            Err(CommandError::Feedback(FeedbackError::InvalidAngle))
+        );
+    }
+
+    fn set_output_mode(state: &RotaryEncoderState, mode: OutputMode) {
+        let mut settings = state.get_settings();
+        settings.output_mode = mode;
+        state.set_settings(settings);
+    }
+
+    #[test]
+    fn level_output_follows_the_ram_back() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        assert!(controller.step(moving(0), 5000), "Level output has no time limit");
+        assert!(!controller.step(moving(-1), 5001), "Off as soon as the ram drops below the target");
+    }
+
+    #[test]
+    fn pulse_output_switches_off_after_its_width() {
+        let (mut controller, state) = make_controller();
+        set_output_mode(&state, OutputMode::Pulse { width_ms: 100 });
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        assert!(controller.step(moving(0), 99));
+        assert!(!controller.step(moving(0), 100), "Off after 100 ms with the ram still at the target");
+        assert!(!controller.step(moving(1), 150));
+    }
+
+    #[test]
+    fn dwell_output_waits_at_target_before_reversing() {
+        let (mut controller, state) = make_controller();
+        set_output_mode(&state, OutputMode::Dwell { dwell_ms: 200 });
+        let hysteresis = Hysteresis { release_band: 1.0, ..Hysteresis::default() };
+        state.set_settings(Settings { hysteresis, ..state.get_settings() });
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(!drive_to(&mut controller, &state, 10), "No output while dwelling");
+        assert_eq!(state.get_machine_state(), MachineState::Returning);
+        assert!(!controller.step(moving(0), 199));
+        assert!(controller.step(moving(1), 200), "Output switches on when the dwell ends");
+        assert!(controller.step(moving(-1), 201));
+        assert!(!controller.step(moving(-1), 202), "Release band counts from 11°, where the dwell ended");
+    }
+
+    #[test]
+    fn toggle_output_flips_at_each_trigger() {
+        let (mut controller, state) = make_controller();
+        set_output_mode(&state, OutputMode::Toggle);
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        return_to_top(&mut controller, &state);
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+        assert!(state.is_output_on(), "Held on through the return and the next stroke");
+        assert!(drive_to(&mut controller, &state, 19));
+        assert!(!drive_to(&mut controller, &state, 20), "Second trigger switches it off");
+    }
+
+    #[test]
+    fn output_mode_is_set_per_bend() {
+        let (mut controller, state) = make_controller();
+        let pulse = BendOptions { output_mode: Some(OutputMode::Pulse { width_ms: 50 }), ..Default::default() };
+        state.set_program(vec![10.0, 10.0], vec![BendOptions::default(), pulse]).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        assert!(controller.step(moving(0), 100), "First bend uses the level setting");
+        return_to_top(&mut controller, &state);
+        drive_to(&mut controller, &state, 10);
+        assert!(!controller.step(moving(0), 100), "Second bend pulses for 50 ms");
+    }
+
+    #[test]
+    fn zero_pulse_width_is_rejected_at_start() {
+        let (_controller, state) = make_controller();
+        let pulse = BendOptions { output_mode: Some(OutputMode::Pulse { width_ms: 0 }), ..Default::default() };
+        assert_eq!(
+            state.set_program(vec![10.0, 20.0], vec![BendOptions::default(), pulse]),
+            Err(StartError::InvalidOutputMode { bend: 2 })
+        );
+    }
+
+    #[test]
+    fn pause_mid_stroke_takes_effect_at_top() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(controller.apply(Command::Pause), Ok(()));
+        controller.step(moving(0), 0);
+        assert!(state.get_snapshot().pause_pending);
+        assert!(drive_to(&mut controller, &state, 10), "The current stroke finishes normally");
+        return_to_top(&mut controller, &state);
+        assert_eq!(state.get_machine_state(), MachineState::Paused);
+        assert_eq!(state.get_current_target_index(), 1);
+        assert_eq!(state.get_current_run(), 1);
+        assert!(!state.get_snapshot().pause_pending);
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::Paused);
+    }
+
+    #[test]
+    fn paused_job_holds_output_off_and_resumes_at_next_bend() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        assert_eq!(controller.apply(Command::Pause), Ok(()));
+        assert_eq!(state.get_machine_state(), MachineState::Paused, "Pause at the top is immediate");
+        assert!(!drive_to(&mut controller, &state, 12), "No trigger while paused");
+        return_to_top(&mut controller, &state);
+        assert_eq!(controller.apply(Command::Resume), Ok(()));
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+        assert!(drive_to(&mut controller, &state, 10));
+        assert_eq!(state.get_current_target_index(), 0);
+    }
+
+    #[test]
+    fn paused_job_is_not_idle_timed_out() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.idle_timeout_ms = 1_000;
+        state.set_settings(settings);
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        controller.apply(Command::Pause).unwrap();
+        controller.step(moving(0), 0);
+        controller.step(moving(0), 5_000);
+        assert_eq!(state.get_machine_state(), MachineState::Paused);
+    }
+
+    #[test]
+    fn resume_cancels_pending_pause() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 6);
+        controller.apply(Command::Pause).unwrap();
+        assert_eq!(controller.apply(Command::Resume), Ok(()));
+        drive_to(&mut controller, &state, 10);
+        return_to_top(&mut controller, &state);
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+    }
+
+    #[test]
+    fn pause_and_resume_rejected_without_job() {
+        let (mut controller, _state) = make_controller();
+        assert!(matches!(controller.apply(Command::Pause), Err(CommandError::IllegalTransition(_))));
+        assert!(matches!(controller.apply(Command::Resume), Err(CommandError::IllegalTransition(_))));
+    }
+
+    #[test]
+    fn repeat_redoes_last_bend() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        assert_eq!(
+            controller.apply(Command::Step(StepCommand::Repeat)),
+            Err(CommandError::Navigation(NavigationError::NothingToRepeat))
+        );
+        drive_to(&mut controller, &state, 10);
+        return_to_top(&mut controller, &state);
+        assert_eq!(state.get_current_target_index(), 1);
+        assert_eq!(controller.apply(Command::Step(StepCommand::Repeat)), Ok(()));
+        assert_eq!(state.get_current_target_index(), 0);
+        assert!(!drive_to(&mut controller, &state, 9), "Bend 1 is the target again");
+        assert!(drive_to(&mut controller, &state, 10));
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::StepChanged);
+    }
+
+    #[test]
+    fn skip_and_jump_move_within_program() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.number_of_runs = 2;
+        state.set_settings(settings);
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        controller.step(moving(0), 0);
+        assert_eq!(controller.apply(Command::Step(StepCommand::Skip)), Ok(()));
+        assert_eq!(state.get_current_target_index(), 1);
+        assert_eq!(controller.apply(Command::Step(StepCommand::Skip)), Ok(()));
+        assert_eq!((state.get_current_target_index(), state.get_current_run()), (0, 2));
+        assert_eq!(controller.apply(Command::Step(StepCommand::Jump { bend: 2, run: Some(1) })), Ok(()));
+        assert_eq!((state.get_current_target_index(), state.get_current_run()), (1, 1));
+        assert_eq!(controller.apply(Command::Step(StepCommand::Back)), Ok(()));
+        assert_eq!(state.get_current_target_index(), 0);
+    }
+
+    #[test]
+    fn navigation_rejected_mid_stroke() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(
+            controller.apply(Command::Step(StepCommand::Skip)),
+            Err(CommandError::Navigation(NavigationError::NotAtTop(MachineState::Bending)))
+        );
+        assert_eq!(state.get_current_target_index(), 0);
+    }
+
+    #[test]
+    fn navigation_allowed_while_paused() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        controller.apply(Command::Pause).unwrap();
+        assert_eq!(controller.apply(Command::Step(StepCommand::Skip)), Ok(()));
+        assert_eq!(state.get_machine_state(), MachineState::Paused);
+        assert_eq!(state.get_current_target_index(), 1);
+    }
+
+    #[test]
+    fn editing_next_bend_keeps_position() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.number_of_runs = 2;
+        state.set_settings(settings);
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        return_to_top(&mut controller, &state);
+        assert_eq!(controller.apply(Command::EditProgram(ProgramEdit::Set { bend: 2, angle: 15.0 })), Ok(()));
+        assert_eq!(state.get_target_angles(), vec![10.0, 15.0]);
+        assert_eq!((state.get_current_target_index(), state.get_current_run()), (1, 1));
+        assert_eq!(state.get_value(), 0);
+        assert!(drive_to(&mut controller, &state, 15), "The edited target is used for the next stroke");
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::ProgramEdited);
+    }
+
+    #[test]
+    fn bend_in_progress_cannot_be_edited() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(
+            controller.apply(Command::EditProgram(ProgramEdit::Set { bend: 1, angle: 8.0 })),
+            Err(CommandError::Edit(EditError::AlreadyExecuted { bend: 1, first_editable: 2 }))
+        );
+        let insert = ProgramEdit::Insert { bend: 2, angle: 12.0, options: BendOptions::default() };
+        assert_eq!(controller.apply(Command::EditProgram(insert)), Ok(()));
+        assert!(drive_to(&mut controller, &state, 10), "Bend in progress keeps its target");
+        return_to_top(&mut controller, &state);
+        assert!(!drive_to(&mut controller, &state, 11));
+        assert!(drive_to(&mut controller, &state, 12), "Inserted bend is next");
+    }
+
+    #[test]
+    fn deleting_last_upcoming_bend_finishes_run() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        assert_eq!(controller.apply(Command::EditProgram(ProgramEdit::Delete { bend: 2 })), Ok(()));
+        return_to_top(&mut controller, &state);
+        controller.step(moving(0), 0);
+        assert_eq!(state.get_machine_state(), MachineState::Idle, "Last bend done, job ends");
+    }
+
+    #[test]
+    fn edit_rejected_without_job() {
+        let (mut controller, _state) = make_controller();
+        assert_eq!(
+            controller.apply(Command::EditProgram(ProgramEdit::Delete { bend: 1 })),
+            Err(CommandError::Edit(EditError::NoJob))
+        );
+    }
+
+    #[test]
+    fn teach_appends_stroke_peak_to_target_list() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![90.0], Vec::new()).unwrap();
+        state.stop();
+        controller.step(moving(0), 0);
+        assert_eq!(controller.apply(Command::SetTeachMode(true)), Ok(()));
+        assert!(state.get_target_angles().is_empty(), "Teach starts a new list");
+        assert_eq!(
+            controller.apply(Command::Teach(TeachSource::Peak)),
+            Err(CommandError::Teach(TeachError::NoPeak))
+        );
+        for _ in 0..42 {
+            controller.step(moving(1), 0);
+        }
+        controller.step(moving(-1), 0);
+        return_to_top(&mut controller, &state);
+        assert_eq!(state.get_snapshot().teach_peak, Some(42.0));
+        assert_eq!(controller.apply(Command::Teach(TeachSource::Peak)), Ok(()));
+        drive_to(&mut controller, &state, 30);
+        assert_eq!(controller.apply(Command::Teach(TeachSource::Current)), Ok(()));
+        assert_eq!(state.get_target_angles(), vec![42.0, 30.0]);
+        assert_eq!(state.get_machine_state(), MachineState::Idle, "Teaching never starts a job");
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::Taught);
+    }
+
+    #[test]
+    fn teach_mode_rejected_while_running_and_ended_by_start() {
+        let (mut controller, state) = make_controller();
+        assert_eq!(
+            controller.apply(Command::Teach(TeachSource::Current)),
+            Err(CommandError::Teach(TeachError::NotInTeachMode))
+        );
+        controller.apply(Command::SetTeachMode(true)).unwrap();
+        controller.apply(Command::Start { angles: vec![10.0], bends: Vec::new() }).unwrap();
+        controller.step(moving(0), 0);
+        assert!(!state.get_snapshot().teach_mode);
+        assert_eq!(
+            controller.apply(Command::SetTeachMode(true)),
+            Err(CommandError::Teach(TeachError::Busy(MachineState::Armed)))
+        );
+    }
+
+    #[test]
+    fn set_zero_loads_offset_at_rest() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.zero_offset = 1.5;
+        settings.resolution = EncoderResolution::from(StepMode::Half);
+        state.set_settings(settings);
+        drive_to(&mut controller, &state, 17);
+        assert_eq!(controller.apply(Command::SetZero), Ok(()));
+        assert_eq!(state.get_value(), 3);
+        assert_eq!(state.get_angle(), 1.5);
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::ZeroSet);
+    }
+
+    #[test]
+    fn set_zero_rejected_mid_stroke() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 6);
+        assert_eq!(
+            controller.apply(Command::SetZero),
+            Err(CommandError::ZeroRejected(MachineState::Bending))
+        );
+        assert_eq!(state.get_value(), 6);
+    }
+
+    #[test]
+    fn reference_input_rezeroes_on_rising_edge() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.reference_input_enabled = true;
+        state.set_settings(settings);
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 12);
+        // Ram comes back up but the count drifted: reset at the threshold leaves it alone
+        while state.get_value() > 2 {
+            controller.step(moving(-1), 0);
+        }
+        controller.step(moving(0), 0);
+        assert_eq!(state.get_value(), 2, "Count only re-zeroed by the reference");
+        let at_reference = ControlInputs { reference_active: true, ..Default::default() };
+        controller.step(at_reference, 0);
+        assert_eq!(state.get_value(), 0);
+        controller.step(ControlInputs { direction: 1, ..at_reference }, 0);
+        controller.step(at_reference, 0);
+        assert_eq!(state.get_value(), 1, "Held input does not re-zero again");
+    }
+
+    #[test]
+    fn reference_input_ignored_when_disabled() {
+        let (mut controller, state) = make_controller();
+        drive_to(&mut controller, &state, 4);
+        controller.step(ControlInputs { reference_active: true, ..Default::default() }, 0);
+        assert_eq!(state.get_value(), 4);
+    }
+
+    #[test]
+    fn two_point_calibration_changes_conversion() {
+        let (mut controller, state) = make_controller();
+        drive_to(&mut controller, &state, 20);
+        let capture = |point, angle| Command::Calibrate(CalibrationCommand::Capture { point, angle });
+        assert_eq!(controller.apply(capture(1, 10.0)), Ok(()));
+        assert_eq!(
+            controller.apply(Command::Calibrate(CalibrationCommand::Apply)),
+            Err(CommandError::Calibration(CalibrationError::MissingPoint(2)))
+        );
+        drive_to(&mut controller, &state, 180);
+        assert_eq!(controller.apply(capture(2, 90.0)), Ok(()));
+        assert_eq!(controller.apply(Command::Calibrate(CalibrationCommand::Apply)), Ok(()));
+
+        let calibration = state.get_settings().calibration.unwrap();
+        assert_eq!(calibration.degrees_per_step, 0.5);
+        assert_eq!(state.get_angle(), 90.0);
+        controller.step(moving(0), 0);
+        assert_eq!(state.get_snapshot().angle, 90.0);
+
+        // Targets are converted with the calibration too
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        assert_eq!(state.get_target_angles(), vec![45.0]);
+        assert!(!drive_to(&mut controller, &state, 89));
+        assert!(drive_to(&mut controller, &state, 90));
+    }
+
+    #[test]
+    fn calibration_rejected_while_running() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert_eq!(
+            controller.apply(Command::Calibrate(CalibrationCommand::Clear)),
+            Err(CommandError::Calibration(CalibrationError::Busy(MachineState::Armed)))
+        );
+        state.stop();
+        controller.step(moving(0), 0);
+        assert_eq!(
+            controller.apply(Command::Calibrate(CalibrationCommand::Capture { point: 3, angle: 0.0 })),
+            Err(CommandError::Calibration(CalibrationError::InvalidPoint(3)))
+        );
+    }
+
+    #[test]
+    fn conversion_settings_locked_while_running() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        let mut settings = state.get_settings();
+        settings.resolution = EncoderResolution::from(StepMode::Half);
+        assert_eq!(
+            controller.apply(Command::UpdateSettings(settings.clone())),
+            Err(CommandError::ConversionLocked(MachineState::Armed))
+        );
+        assert_ne!(state.get_settings().resolution, settings.resolution);
+
+        // Other fields still apply mid-job
+        let tolerant = Settings { accept_tolerance: 0.5, ..state.get_settings() };
+        assert_eq!(controller.apply(Command::UpdateSettings(tolerant)), Ok(()));
+        assert_eq!(state.get_settings().accept_tolerance, 0.5);
+
+        state.stop();
+        controller.step(moving(0), 0);
+        assert_eq!(controller.apply(Command::UpdateSettings(settings.clone())), Ok(()));
+        assert_eq!(state.get_settings().resolution, settings.resolution);
+    }
+
+    #[test]
+    fn start_command_arms_job() {
+        let (mut controller, state) = make_controller();
+        let start = Command::Start { angles: vec![10.0], bends: Vec::new() };
+        assert_eq!(controller.apply(start), Ok(()));
+        assert_eq!(state.get_machine_state(), MachineState::Armed);
+    }
+
+    #[test]
+    fn start_command_rejected_mid_stroke() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 10);
+        let start = Command::Start { angles: vec![20.0], bends: Vec::new() };
+        assert_eq!(
+            controller.apply(start),
+            Err(CommandError::Start(StartError::IllegalState(MachineState::Returning)))
+        );
+        assert_eq!(state.get_value(), 10, "A rejected Start must not reset the count");
+    }
+
+    #[test]
+    fn acknowledge_all_command_reports_active_alarms() {
+        let (mut controller, _state) = make_controller();
+        controller.step(guard_open(), 10);
+        assert_eq!(
+            controller.apply(Command::Acknowledge(None)),
+            Err(CommandError::AlarmsStillActive(vec![AlarmCode::Interlock]))
+        );
+        controller.step(moving(0), 20);
+        assert_eq!(controller.apply(Command::Acknowledge(None)), Ok(()));
+    }
+
+    #[test]
+    fn abort_command_without_safe_stop_is_rejected() {
+        let (mut controller, _state) = make_controller();
+        assert_eq!(controller.apply(Command::AbortSafeStop), Err(CommandError::NoSafeStop));
+    }
+
+    #[test]
+    fn interlock_forces_output_off_at_target() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        assert!(!controller.step(guard_open(), 100));
+        assert!(!state.is_output_on());
+        assert!(!state.is_active(), "Interlock must abort the running job");
+        assert!(state.get_interlock_status().fault_latched);
+    }
+
+    #[test]
+    fn interlock_overrides_manual_output() {
+        let (mut controller, state) = make_controller();
+        state.set_manual_output(true).unwrap();
+        assert!(controller.step(moving(0), 0));
+        assert!(!controller.step(guard_open(), 10));
+        assert_ne!(state.get_machine_state(), MachineState::Manual, "Interlock must clear the manual override");
+    }
+
+    #[test]
+    fn manual_output_cleared_without_keepalive() {
+        let (mut controller, state) = make_controller();
+        let timeout_ms = Settings::default().manual_keepalive_timeout_ms as u64;
+        controller.apply(Command::SetManual(true)).unwrap();
+        assert!(controller.step(moving(0), 100));
+        controller.apply(Command::SetManual(true)).unwrap();
+        assert!(controller.step(moving(0), 100 + timeout_ms - 1), "Keep-alive renews the override");
+        assert!(controller.step(moving(0), 100 + 2 * timeout_ms - 2));
+        assert!(!controller.step(moving(0), 100 + 2 * timeout_ms - 1));
+        assert_ne!(state.get_machine_state(), MachineState::Manual);
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::ManualExpired);
+    }
+
+    #[test]
+    fn manual_output_limited_to_max_on_time() {
+        let (mut controller, state) = make_controller();
+        let max_on_ms = Settings::default().manual_max_on_ms as u64;
+        let mut now_ms = 0;
+        controller.apply(Command::SetManual(true)).unwrap();
+        while now_ms < max_on_ms {
+            assert!(controller.step(moving(0), now_ms));
+            controller.apply(Command::SetManual(true)).unwrap();
+            now_ms += 100;
+        }
+        assert!(!controller.step(moving(0), now_ms), "Keep-alives must not extend the on-time");
+        assert_ne!(state.get_machine_state(), MachineState::Manual);
+    }
+
+    #[test]
+    fn zero_keepalive_timeout_latches_manual_output() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings { manual_keepalive_timeout_ms: 0, manual_max_on_ms: 0, ..Settings::default() });
+        controller.apply(Command::SetManual(true)).unwrap();
+        assert!(controller.step(moving(0), 0));
+        assert!(controller.step(moving(0), 600_000));
+        assert_eq!(state.get_machine_state(), MachineState::Manual);
+    }
+
+    #[test]
+    fn interlock_overrides_safe_stop() {
+        let (mut controller, state) = make_controller();
+        state.set_value(100);
+        state.stop();
+        assert!(controller.step(moving(0), 0), "Safe stop drives the output ON");
+        assert!(!controller.step(guard_open(), 10));
+        assert!(!state.is_safe_stop_active());
+    }
+
+    #[test]
+    fn output_stays_off_until_fault_acknowledged() {
+        let (mut controller, state) = make_controller();
+        controller.step(guard_open(), 10);
+        controller.step(moving(0), 20);
+        assert!(state.set_manual_output(true).is_err(), "Manual output is rejected while faulted");
+        assert!(!controller.step(moving(0), 30), "Latched fault must keep the output off");
+        assert_eq!(state.acknowledge_alarm(AlarmCode::Interlock), Ok(()));
+        assert_eq!(state.get_machine_state(), MachineState::Idle);
+        state.set_manual_output(true).unwrap();
+        assert!(controller.step(moving(0), 40));
+    }
+
+    #[test]
+    fn start_rejected_while_fault_latched() {
+        let (mut controller, state) = make_controller();
+        controller.step(guard_open(), 10);
+        controller.step(moving(0), 20);
+        assert_eq!(
+            state.set_program(vec![45.0], Vec::new()),
+            Err(StartError::AlarmActive(AlarmCode::Interlock))
+        );
+        assert!(!state.is_active());
+        state.acknowledge_alarm(AlarmCode::Interlock).unwrap();
+        assert_eq!(state.set_program(vec![45.0], Vec::new()), Ok(()));
+    }
+
+    #[test]
+    fn disabled_interlock_is_ignored() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings::default());
+        state.set_manual_output(true).unwrap();
+        assert!(controller.step(guard_open(), 10));
+        assert!(state.get_latched_alarms().is_empty());
+    }
+
+    #[test]
+    fn acknowledge_rejected_while_interlock_open() {
+        let (mut controller, state) = make_controller();
+        controller.step(guard_open(), 10);
+        assert_eq!(
+            state.acknowledge_alarm(AlarmCode::Interlock),
+            Err(AlarmError::ConditionActive(AlarmCode::Interlock))
+        );
+    }
+
+    #[test]
+    fn blocking_alarm_forces_output_off_and_aborts_job() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+        state.raise_alarm(AlarmCode::SafeStopTimeout, "test", 50);
+        assert!(!controller.step(moving(0), 60));
+        assert!(!state.is_active());
+    }
+
+    #[test]
+    fn warning_alarm_does_not_block_output() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        state.raise_alarm(AlarmCode::StorageFailure, "test", 0);
+        assert!(drive_to(&mut controller, &state, 10));
+        assert!(state.is_active());
+    }
+
+    #[test]
+    fn overtravel_within_tolerance_is_ignored() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 15);
+        assert!(state.get_latched_alarms().is_empty());
+        assert_eq!(state.get_last_overtravel(), None);
+    }
+
+    #[test]
+    fn overtravel_past_tolerance_raises_alarm_and_records_peak() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 18);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::Overtravel));
+        let record = state.get_last_overtravel().expect("overtravel must be recorded");
+        assert_eq!(record.bend_index, 0);
+        assert_eq!(record.run, 1);
+        assert!((record.target_angle - 10.0).abs() < 1e-6);
+        assert!((record.peak_angle - 18.0).abs() < 1e-6, "Peak must follow the ram past the alarm point");
+        assert!(state.is_active(), "Overtravel is a warning and does not abort the job by default");
+    }
+
+    #[test]
+    fn per_bend_tolerance_overrides_setting() {
+        let (mut controller, state) = make_controller();
+        let options = vec![BendOptions { overtravel_tolerance: Some(1.0), ..Default::default() }];
+        state.set_program(vec![10.0], options).unwrap();
+        drive_to(&mut controller, &state, 12);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::Overtravel));
+    }
+
+    #[test]
+    fn overtravel_safe_stop_reverses_machine() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.overtravel_safe_stop = true;
+        state.set_settings(settings);
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 16);
+        assert!(state.is_safe_stop_active());
+        assert!(controller.step(moving(1), 0), "Safe stop keeps the output ON to reverse the ram");
+        let record = state.get_last_overtravel().unwrap();
+        assert!((record.peak_angle - 17.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn overtravel_condition_clears_when_ram_returns() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0, 20.0], Vec::new()).unwrap();
+        drive_to(&mut controller, &state, 16);
+        while state.get_value() > 0 {
+            controller.step(moving(-1), 0);
+        }
+        controller.step(moving(0), 0);
+        assert_eq!(state.acknowledge_alarm(AlarmCode::Overtravel), Ok(()));
+    }
+
+    #[test]
+    fn no_motion_mid_bend_raises_stall_warning() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        controller.step(moving(0), 1000);
+        for _ in 0..10 {
+            controller.step(moving(1), 1000);
+        }
+        controller.step(moving(0), 3999);
+        assert!(!state.is_stalled());
+        controller.step(moving(0), 4000);
+        assert!(state.is_stalled());
+        assert!(state.get_latched_alarms().contains(&AlarmCode::Stall));
+        assert!(state.is_active(), "A stall is a warning and must not end the job");
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::Stall);
+    }
+
+    #[test]
+    fn waiting_at_top_is_not_a_stall() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        controller.step(moving(0), 0);
+        controller.step(moving(0), 60_000);
+        assert!(!state.is_stalled());
+        assert!(state.get_latched_alarms().is_empty());
+    }
+
+    #[test]
+    fn motion_clears_stall() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        controller.step(moving(0), 0);
+        for _ in 0..10 {
+            controller.step(moving(1), 0);
+        }
+        controller.step(moving(0), 5000);
+        assert!(state.is_stalled());
+        controller.step(moving(1), 5001);
+        assert!(!state.is_stalled());
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::MotionResumed);
+        assert_eq!(state.acknowledge_alarm(AlarmCode::Stall), Ok(()));
+    }
+
+    #[test]
+    fn idle_timeout_ends_job() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.idle_timeout_ms = 10_000;
+        state.set_settings(settings);
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        controller.step(moving(0), 100);
+        controller.step(moving(0), 10_099);
+        assert!(state.is_active());
+        controller.step(moving(0), 10_100);
+        assert!(!state.is_active());
+        assert!(state.get_events().iter().any(|e| e.kind == EventKind::IdleTimeout));
+    }
+
+    #[test]
+    fn idle_time_counts_from_job_start() {
+        let (mut controller, state) = make_controller();
+        controller.step(moving(0), 0);
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        controller.step(moving(0), 50_000);
+        assert_eq!(state.get_snapshot().idle_ms, 0);
+        controller.step(moving(0), 50_250);
+        assert_eq!(state.get_snapshot().idle_ms, 250);
+    }
+
+    #[test]
+    fn safe_stop_completion_reports_duration() {
+        let (mut controller, state) = make_controller();
+        state.set_value(20);
+        state.stop();
+        assert!(controller.step(moving(0), 1000));
+        for _ in 0..20 {
+            controller.step(moving(-1), 1500);
+        }
+        assert!(!state.is_safe_stop_active());
+        let report = state.get_last_safe_stop().unwrap();
+        assert_eq!(report.outcome, SafeStopOutcome::Completed);
+        assert_eq!(report.duration_ms, 500);
+        assert_eq!(state.get_events().last().unwrap().kind, EventKind::SafeStopCompleted);
+    }
+
+    #[test]
+    fn safe_stop_timeout_turns_output_off_and_raises_fault() {
+        let (mut controller, state) = make_controller();
+        state.set_value(100);
+        state.stop();
+        assert!(controller.step(moving(0), 0));
+        assert!(controller.step(moving(0), 9_999));
+        assert!(!controller.step(moving(0), 10_000));
+        assert!(!state.is_safe_stop_active());
+        assert!(state.get_latched_alarms().contains(&AlarmCode::SafeStopTimeout));
+        assert!(!controller.step(moving(0), 10_001), "Output stays off while the fault is latched");
+        let report = state.get_last_safe_stop().unwrap();
+        assert_eq!(report.outcome, SafeStopOutcome::TimedOut);
+        assert_eq!(report.duration_ms, 10_000);
+    }
+
+    #[test]
+    fn safe_stop_timeout_can_be_disabled() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.safe_stop_timeout_ms = 0;
+        state.set_settings(settings);
+        state.set_value(100);
+        state.stop();
+        controller.step(moving(0), 0);
+        assert!(controller.step(moving(0), 1_000_000));
+        assert!(state.is_safe_stop_active());
+    }
+
+    #[test]
+    fn aborted_safe_stop_turns_output_off() {
+        let (mut controller, state) = make_controller();
+        state.set_value(100);
+        state.stop();
+        assert!(controller.step(moving(0), 0));
+        assert!(state.abort_safe_stop());
+        assert!(!controller.step(moving(0), 250));
+        let report = state.get_last_safe_stop().unwrap();
+        assert_eq!(report.outcome, SafeStopOutcome::Cancelled);
+        assert_eq!(report.duration_ms, 250);
+        assert!(state.get_latched_alarms().is_empty());
+    }
+
+    #[test]
+    fn poisoned_lock_faults_machine_instead_of_panicking() {
+        let (mut controller, state) = make_controller();
+        state.set_program(vec![10.0], Vec::new()).unwrap();
+        assert!(drive_to(&mut controller, &state, 10));
+
+        let settings = state.settings.clone();
+        let result = std::thread::spawn(move || {
+            let _guard = settings.lock().unwrap();
+            panic!("web handler panicked while holding the settings");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let _ = state.get_settings();
+        assert!(!controller.step(moving(0), 100), "Output must go off after a poisoned lock");
+        assert_eq!(state.get_machine_state(), MachineState::Fault);
+        assert_eq!(
+            state.acknowledge_alarm(AlarmCode::InternalError),
+            Err(AlarmError::ConditionActive(AlarmCode::InternalError))
+        );
+    }
+
+    #[test]
+    fn saturated_count_raises_encoder_error() {
+        let (mut controller, state) = make_controller();
+        state.set_value(720);
+        controller.step(moving(1), 10);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::EncoderError));
+        assert!(state.is_alarm_blocking());
+    }
+
+    #[test]
+    fn saturation_follows_the_forward_direction() {
+        let (mut controller, state) = make_controller();
+        state.set_settings(Settings { forward_direction: ForwardDirection::CounterClockwise, ..state.get_settings() });
+        state.set_value(720);
+        controller.step(moving(1), 10);
+        assert!(state.get_latched_alarms().is_empty(), "Clockwise is backwards, away from the limit");
+        controller.step(moving(-1), 20);
+        controller.step(moving(-1), 30);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::EncoderError));
+    }
+
+    #[test]
+    fn saturation_at_negative_travel_limit_raises_encoder_error() {
+        let (mut controller, state) = make_controller();
+        let mut settings = state.get_settings();
+        settings.travel.signed = true;
+        settings.travel.min_angle = -10.0;
+        state.set_settings(settings);
+        state.set_value(state.get_settings().step_range().min);
+        controller.step(moving(-1), 10);
+        assert!(state.get_latched_alarms().contains(&AlarmCode::EncoderError));
+    }
+
+    #[test]
+    fn reverse_counts_at_zero_are_not_saturation() {
+        let (mut controller, state) = make_controller();
+        controller.step(moving(-1), 10);
+        controller.step(moving(-1), 20);
+        assert!(state.get_latched_alarms().is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+
+// Number of events kept for the API
+const EVENT_LOG_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum EventKind {
+    /// No encoder movement mid-bend for longer than the stall timeout
+    Stall,
+    /// Encoder movement resumed after a stall
+    MotionResumed,
+    /// The job was ended because the encoder was idle for too long
+    IdleTimeout,
+    /// Safe stop finished with the ram back at the top
+    SafeStopCompleted,
+    /// Safe stop did not finish within the safe stop timeout
+    SafeStopTimeout,
+    /// Safe stop was aborted through the API
+    SafeStopAborted,
+    /// The job was paused with the ram at the top
+    Paused,
+    /// A paused job was resumed
+    Resumed,
+    /// The operator moved to another bend or run
+    StepChanged,
+    /// An upcoming bend was changed, inserted or deleted mid-job
+    ProgramEdited,
+    /// An angle was taught and appended to the target list
+    Taught,
+    /// The count was zeroed from the API
+    ZeroSet,
+    /// A two-point calibration was applied
+    Calibrated,
+    /// Manual output override cleared by the keep-alive timeout or the on-time limit
+    ManualExpired,
+    /// An operator-measured angle adjusted the compensation of a bend
+    Feedback,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub timestamp_ms: u64,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+/// Bounded log of notable control events, oldest first.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, kind: EventKind, message: impl Into<String>, timestamp_ms: u64) {
+        if self.events.len() == EVENT_LOG_LEN {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            timestamp_ms,
+            kind,
+            message: message.into(),
+        });
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_kept_in_order() {
+        let mut log = EventLog::new();
+        log.push(EventKind::Stall, "stalled", 10);
+        log.push(EventKind::MotionResumed, "moving", 20);
+        let events = log.events();
+        assert_eq!(events[0].kind, EventKind::Stall);
+        assert_eq!(events[1].kind, EventKind::MotionResumed);
+    }
+
+    #[test]
+    fn log_is_bounded() {
+        let mut log = EventLog::new();
+        for i in 0..(EVENT_LOG_LEN as u64 + 5) {
+            log.push(EventKind::Stall, "stalled", i);
+        }
+        let events = log.events();
+        assert_eq!(events.len(), EVENT_LOG_LEN);
+        assert_eq!(events[0].timestamp_ms, 5);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::travel::OutOfTravel;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+
+// Measurements kept for review
+const FEEDBACK_HISTORY_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeedbackError {
+    /// No bend has been completed since the last Start
+    NoBend,
+    /// The measured bend is no longer in the program
+    BendRemoved { bend: usize },
+    /// The program was edited or reloaded after the bend was made
+    ProgramChanged { bend: usize },
+    InvalidAngle,
+    /// The compensated target would be outside the travel range
+    OutOfTravel(OutOfTravel),
+}
+
+impl fmt::Display for FeedbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedbackError::NoBend => write!(f, "No bend completed since Start"),
+            FeedbackError::BendRemoved { bend } => write!(f, "Bend {} is no longer in the program", bend),
+            FeedbackError::ProgramChanged { bend } => write!(f, "Program changed since bend {} was made", bend),
+            FeedbackError::InvalidAngle => write!(f, "Measured angle must be a number of degrees"),
+            FeedbackError::OutOfTravel(e) => write!(f, "Compensated target out of range: {}", e),
+        }
+    }
+}
+
+/// One operator measurement and the compensation it led to.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub struct FeedbackRecord {
+    pub timestamp_ms: u64,
+    pub run: i32,
+    pub bend: usize,
+    pub target_angle: f32,
+    pub measured_angle: f32,
+    /// Measured minus target
+    pub deviation: f32,
+    /// Compensation the bend was made with
+    pub previous_compensation: f32,
+    /// Compensation used for the bend from now on
+    pub compensation: f32,
+}
+
+/// Compensation that makes the part come out at `target_angle`, assuming it
+/// springs back by the same amount as the measured one. Derived from the
+/// compensation the measured bend was made with, so measuring it again
+/// replaces the correction instead of adding to it.
+pub fn corrected_compensation(used: f32, target_angle: f32, measured_angle: f32) -> f32 {
+    used - (measured_angle - target_angle)
+}
+
+/// Bounded history of measurements, oldest first. Kept across jobs.
+#[derive(Debug, Default)]
+pub struct FeedbackLog {
+    records: VecDeque<FeedbackRecord>,
+}
+
+impl FeedbackLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, record: FeedbackRecord) {
+        if self.records.len() == FEEDBACK_HISTORY_LEN {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn records(&self) -> Vec<FeedbackRecord> {
+        self.records.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn springback_is_added_to_the_compensation() {
+        // Made with no compensation, sprang back 2° short of 90°
+        assert_eq!(corrected_compensation(0.0, 90.0, 88.0), 2.0);
+        // Made with 2°, still 0.5° short
+        assert_eq!(corrected_compensation(2.0, 90.0, 89.5), 2.5);
+        // Made with 2°, 1° over
+        assert_eq!(corrected_compensation(2.0, 90.0, 91.0), 1.0);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut log = FeedbackLog::new();
+        for bend in 1..=FEEDBACK_HISTORY_LEN + 1 {
+            log.push(FeedbackRecord {
+                timestamp_ms: 0,
+                run: 1,
+                bend,
+                target_angle: 90.0,
+                measured_angle: 88.0,
+                deviation: -2.0,
+                previous_compensation: 0.0,
+                compensation: 2.0,
+            });
+        }
+        let records = log.records();
+        assert_eq!(records.len(), FEEDBACK_HISTORY_LEN);
+        assert_eq!(records[0].bend, 2);
+    }
+}
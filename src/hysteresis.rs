@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+/// Switching thresholds around a bend, in degrees. The bands keep encoder
+/// chatter at a threshold from toggling the output or ending a stroke twice.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Hysteresis {
+    /// Added to the target to get the trigger point; negative fires early
+    pub trigger_offset: f32,
+    /// The output releases once the ram is this far back below the trigger point
+    pub release_band: f32,
+    /// After a stroke ends at the minimum angle threshold, the ram must go this
+    /// far above the threshold before the next stroke can end
+    pub rearm_band: f32,
+}
+
+impl Default for Hysteresis {
+    /// Releases as soon as the ram drops below the target, as before the bands
+    /// were configurable, and re-arms at 5° with the default 2.5° minimum angle threshold
+    fn default() -> Self {
+        Self {
+            trigger_offset: 0.0,
+            release_band: 0.0,
+            rearm_band: 2.5,
+        }
+    }
+}
+
+/// Per-bend overrides sent in `BendOptions`. Unset fields fall back to `Settings`.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HysteresisOverride {
+    pub trigger_offset: Option<f32>,
+    pub release_band: Option<f32>,
+    pub rearm_band: Option<f32>,
+}
+
+/// Thresholds of one bend's stroke, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    /// The output fires at or above this angle
+    pub trigger: f32,
+    /// A fired output turns off below this angle
+    pub release: f32,
+    /// The stroke ends below this angle (the minimum angle threshold)
+    pub reset: f32,
+    /// The next stroke starts above this angle
+    pub rearm: f32,
+}
+
+impl Hysteresis {
+    pub fn with_override(self, bend: &HysteresisOverride) -> Self {
+        Self {
+            trigger_offset: bend.trigger_offset.unwrap_or(self.trigger_offset),
+            release_band: bend.release_band.unwrap_or(self.release_band),
+            rearm_band: bend.rearm_band.unwrap_or(self.rearm_band),
+        }
+    }
+
+    pub fn thresholds(&self, target_angle: f32, reset_angle: f32) -> Thresholds {
+        let trigger = target_angle + self.trigger_offset;
+        Thresholds {
+            trigger,
+            release: trigger - self.release_band,
+            reset: reset_angle,
+            rearm: self.rearm_angle(reset_angle),
+        }
+    }
+
+    /// Angle the ram must go above after a stroke ended at `reset_angle`
+    pub fn rearm_angle(&self, reset_angle: f32) -> f32 {
+        reset_angle + self.rearm_band
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.trigger_offset.is_finite() {
+            return Err("Trigger offset must be a number of degrees".to_string());
+        }
+        for (name, band) in [("Release", self.release_band), ("Re-arm", self.rearm_band)] {
+            if !(band.is_finite() && band >= 0.0) {
+                return Err(format!("{} band must be 0° or more, not {}", name, band));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_are_placed_around_target_and_reset() {
+        let hysteresis = Hysteresis { trigger_offset: -0.5, release_band: 1.0, rearm_band: 2.5 };
+        let thresholds = hysteresis.thresholds(45.0, 2.5);
+        assert_eq!(thresholds, Thresholds { trigger: 44.5, release: 43.5, reset: 2.5, rearm: 5.0 });
+    }
+
+    #[test]
+    fn bend_override_replaces_only_set_fields() {
+        let bend = HysteresisOverride { release_band: Some(3.0), ..Default::default() };
+        let hysteresis = Hysteresis::default().with_override(&bend);
+        assert_eq!(hysteresis.release_band, 3.0);
+        assert_eq!(hysteresis.rearm_band, Hysteresis::default().rearm_band);
+    }
+
+    #[test]
+    fn negative_bands_are_rejected() {
+        let valid = Hysteresis::default();
+        assert!(valid.validate().is_ok());
+        assert!(Hysteresis { trigger_offset: -1.0, ..valid }.validate().is_ok());
+        assert!(Hysteresis { release_band: -1.0, ..valid }.validate().is_err());
+        assert!(Hysteresis { rearm_band: f32::NAN, ..valid }.validate().is_err());
+    }
+}
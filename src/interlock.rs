@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+
+// Number of interlock transitions kept for the API history
+const INTERLOCK_HISTORY_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum InterlockInput {
+    GuardDoor,
+    LightCurtain,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct InterlockEvent {
+    pub input: InterlockInput,
+    pub open: bool,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InterlockStatus {
+    pub guard_door_open: bool,
+    pub light_curtain_open: bool,
+    pub fault_latched: bool,
+    pub trip_count: u32,
+    pub history: Vec<InterlockEvent>,
+}
+
+/// Tracks guard door / light curtain inputs and records their transitions.
+/// Latching the resulting fault is left to the alarm manager.
+#[derive(Debug)]
+pub struct InterlockMonitor {
+    guard_door_open: bool,
+    light_curtain_open: bool,
+    trip_count: u32,
+    history: VecDeque<InterlockEvent>,
+}
+
+impl Default for InterlockMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterlockMonitor {
+    pub fn new() -> Self {
+        Self {
+            guard_door_open: false,
+            light_curtain_open: false,
+            trip_count: 0,
+            history: VecDeque::with_capacity(INTERLOCK_HISTORY_LEN),
+        }
+    }
+
+    /// Record the sampled state of an input. Returns `true` when the input has just opened.
+    pub fn update(&mut self, input: InterlockInput, open: bool, timestamp_ms: u64) -> bool {
+        let current = match input {
+            InterlockInput::GuardDoor => &mut self.guard_door_open,
+            InterlockInput::LightCurtain => &mut self.light_curtain_open,
+        };
+        if *current == open {
+            return false;
+        }
+        *current = open;
+
+        if self.history.len() == INTERLOCK_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(InterlockEvent { input, open, timestamp_ms });
+
+        if open {
+            self.trip_count = self.trip_count.saturating_add(1);
+        }
+        open
+    }
+
+    pub fn status(&self, fault_latched: bool) -> InterlockStatus {
+        InterlockStatus {
+            guard_door_open: self.guard_door_open,
+            light_curtain_open: self.light_curtain_open,
+            fault_latched,
+            trip_count: self.trip_count,
+            history: self.history.iter().copied().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opening_interlock_is_reported() {
+        let mut monitor = InterlockMonitor::new();
+        assert!(monitor.update(InterlockInput::GuardDoor, true, 10));
+        assert!(monitor.status(false).guard_door_open);
+    }
+
+    #[test]
+    fn closing_interlock_is_recorded_but_not_a_trip() {
+        let mut monitor = InterlockMonitor::new();
+        monitor.update(InterlockInput::LightCurtain, true, 10);
+        assert!(!monitor.update(InterlockInput::LightCurtain, false, 20));
+        let status = monitor.status(false);
+        assert!(!status.light_curtain_open);
+        assert_eq!(status.history.len(), 2);
+        assert_eq!(status.trip_count, 1);
+    }
+
+    #[test]
+    fn repeated_samples_do_not_add_history() {
+        let mut monitor = InterlockMonitor::new();
+        monitor.update(InterlockInput::GuardDoor, true, 10);
+        monitor.update(InterlockInput::GuardDoor, true, 11);
+        monitor.update(InterlockInput::GuardDoor, true, 12);
+        let status = monitor.status(false);
+        assert_eq!(status.history.len(), 1);
+        assert_eq!(status.trip_count, 1);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut monitor = InterlockMonitor::new();
+        for i in 0..(INTERLOCK_HISTORY_LEN as u64 * 2) {
+            monitor.update(InterlockInput::GuardDoor, i % 2 == 0, i);
+        }
+        let status = monitor.status(false);
+        assert_eq!(status.history.len(), INTERLOCK_HISTORY_LEN);
+        // Oldest entries are dropped first
+        assert_eq!(status.history[0].timestamp_ms, INTERLOCK_HISTORY_LEN as u64);
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+
+// An output edge with no reversal within this time is not a measurement
+const REVERSAL_TIMEOUT_MS: u64 = 1000;
+
+/// Running statistics of the output-to-reversal latency, in milliseconds.
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq)]
+pub struct LatencyStats {
+    pub samples: u32,
+    pub last_ms: Option<u32>,
+    pub min_ms: Option<u32>,
+    pub max_ms: Option<u32>,
+    pub mean_ms: Option<f32>,
+    /// Output edges the ram did not reverse after within the timeout
+    pub timeouts: u32,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency_ms: u32) {
+        self.samples += 1;
+        self.last_ms = Some(latency_ms);
+        self.min_ms = Some(self.min_ms.map_or(latency_ms, |min| min.min(latency_ms)));
+        self.max_ms = Some(self.max_ms.map_or(latency_ms, |max| max.max(latency_ms)));
+        let mean = self.mean_ms.unwrap_or(0.0);
+        self.mean_ms = Some(mean + (latency_ms as f32 - mean) / self.samples as f32);
+    }
+}
+
+/// Times each rising output edge taken while the ram moves forward against
+/// the first encoder count in the reverse direction after it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyMeter {
+    output_on: bool,
+    // Last non-zero movement, +1 forward
+    last_direction: i32,
+    pending_edge_ms: Option<u64>,
+    stats: LatencyStats,
+}
+
+impl LatencyMeter {
+    /// Feed once per iteration with the movement sampled in it (+1 forward,
+    /// -1 reverse, after the forward direction setting) and the output level
+    /// set at its end.
+    pub fn update(&mut self, output_on: bool, direction: i32, now_ms: u64) {
+        if let Some(edge_ms) = self.pending_edge_ms {
+            let elapsed_ms = now_ms.saturating_sub(edge_ms);
+            if direction < 0 {
+                self.stats.record(elapsed_ms.min(u32::MAX as u64) as u32);
+                self.pending_edge_ms = None;
+            } else if elapsed_ms > REVERSAL_TIMEOUT_MS {
+                self.stats.timeouts += 1;
+                self.pending_edge_ms = None;
+            }
+        }
+        if direction != 0 {
+            self.last_direction = direction;
+        }
+        if output_on && !self.output_on && self.last_direction > 0 {
+            self.pending_edge_ms = Some(now_ms);
+        }
+        self.output_on = output_on;
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats
+    }
+
+    pub fn reset(&mut self) {
+        self.stats = LatencyStats::default();
+        self.pending_edge_ms = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ram moving forward, output fires at `edge_ms`, first reverse count at `reverse_ms`
+    fn stroke(meter: &mut LatencyMeter, edge_ms: u64, reverse_ms: u64) {
+        meter.update(false, 1, edge_ms - 1);
+        for now_ms in edge_ms..reverse_ms {
+            meter.update(true, 0, now_ms);
+        }
+        meter.update(true, -1, reverse_ms);
+        meter.update(false, -1, reverse_ms + 1);
+    }
+
+    #[test]
+    fn latency_is_edge_to_first_reverse_count() {
+        let mut meter = LatencyMeter::default();
+        stroke(&mut meter, 100, 135);
+        stroke(&mut meter, 1000, 1025);
+        stroke(&mut meter, 2000, 2030);
+        let stats = meter.stats();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.last_ms, Some(30));
+        assert_eq!(stats.min_ms, Some(25));
+        assert_eq!(stats.max_ms, Some(35));
+        assert_eq!(stats.mean_ms, Some(30.0));
+    }
+
+    #[test]
+    fn edge_without_reversal_times_out() {
+        let mut meter = LatencyMeter::default();
+        meter.update(false, 1, 0);
+        for now_ms in 1..=REVERSAL_TIMEOUT_MS + 2 {
+            meter.update(true, 0, now_ms);
+        }
+        assert_eq!(meter.stats().timeouts, 1);
+        assert_eq!(meter.stats().samples, 0);
+    }
+
+    #[test]
+    fn edge_while_returning_is_not_measured() {
+        let mut meter = LatencyMeter::default();
+        meter.update(false, -1, 0);
+        meter.update(true, 0, 1);
+        meter.update(true, -1, 5);
+        assert_eq!(meter.stats(), LatencyStats::default());
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use serde::Serialize;
+
+/// Operating mode of the controller. Only `RotaryEncoderState::transition_to`
+/// changes it, and only along the edges allowed by `can_transition_to`.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MachineState {
+    /// No job running, output off
+    Idle,
+    /// Job running, ram at the top waiting for the next stroke
+    Armed,
+    /// Ram moving towards the current target, output off
+    Bending,
+    /// Target reached, output fired, waiting for the ram to return
+    Returning,
+    /// Job held at the top of the stroke, output off, position in the program kept
+    Paused,
+    /// Output held ON to reverse the ram until it is back at the top
+    SafeStop,
+    /// Output driven by the manual override
+    Manual,
+    /// Blocking alarm latched, output forced off until acknowledged
+    Fault,
+}
+
+pub const ALL_STATES: [MachineState; 8] = [
+    MachineState::Idle,
+    MachineState::Armed,
+    MachineState::Bending,
+    MachineState::Returning,
+    MachineState::Paused,
+    MachineState::SafeStop,
+    MachineState::Manual,
+    MachineState::Fault,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: MachineState,
+    pub to: MachineState,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot go from {:?} to {:?}", self.from, self.to)
+    }
+}
+
+impl MachineState {
+    pub fn from_u8(value: u8) -> Self {
+        ALL_STATES.get(value as usize).copied().unwrap_or(MachineState::Fault)
+    }
+
+    /// A job is running (any of the stroke states)
+    pub fn is_running(self) -> bool {
+        matches!(self, MachineState::Armed | MachineState::Bending | MachineState::Returning)
+    }
+
+    /// Start is accepted at rest, at the top of a stroke, while paused, or to
+    /// cancel a safe stop. `Returning -> Armed` is only taken by the control loop itself.
+    pub fn can_start(self) -> bool {
+        matches!(
+            self,
+            MachineState::Idle | MachineState::Armed | MachineState::Paused | MachineState::SafeStop
+        )
+    }
+
+    pub fn can_transition_to(self, to: MachineState) -> bool {
+        use MachineState::*;
+        match (self, to) {
+            // A blocking alarm can interrupt anything
+            (_, Fault) => true,
+            // Only acknowledging the last blocking alarm leaves Fault
+            (Fault, to) => to == Idle,
+            // Start: from rest, restarting a job at the top, resuming, or cancelling a safe stop
+            (Idle | Armed | Paused | SafeStop, Armed) => true,
+            // Stop can always start a safe stop
+            (_, SafeStop) => true,
+            (Idle, Manual) => true,
+            (Manual, Manual | Idle) => true,
+            (Armed, Bending) => true,
+            (Armed | Bending, Returning) => true,
+            (Returning, Armed) => true,
+            // Pause takes effect with the ram at the top of the stroke
+            (Armed | Returning, Paused) => true,
+            (SafeStop, Idle) => true,
+            (Idle, Idle) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MachineState::*;
+
+    #[test]
+    fn from_u8_round_trips_every_state() {
+        for state in ALL_STATES {
+            assert_eq!(MachineState::from_u8(state as u8), state);
+        }
+    }
+
+    #[test]
+    fn from_u8_unknown_value_is_fault() {
+        assert_eq!(MachineState::from_u8(200), Fault);
+    }
+
+    #[test]
+    fn only_stroke_states_are_running() {
+        let running: Vec<_> = ALL_STATES.into_iter().filter(|s| s.is_running()).collect();
+        assert_eq!(running, vec![Armed, Bending, Returning]);
+    }
+
+    #[test]
+    fn transition_table_is_exhaustive() {
+        // Rows: from, columns: to, in ALL_STATES order
+        // Idle, Armed, Bending, Returning, Paused, SafeStop, Manual, Fault
+        let expected: [[bool; 8]; 8] = [
+            /* Idle      */ [true, true, false, false, false, true, true, true],
+            /* Armed     */ [false, true, true, true, true, true, false, true],
+            /* Bending   */ [false, false, false, true, false, true, false, true],
+            /* Returning */ [false, true, false, false, true, true, false, true],
+            /* Paused    */ [false, true, false, false, false, true, false, true],
+            /* SafeStop  */ [true, true, false, false, false, true, false, true],
+            /* Manual    */ [true, false, false, false, false, true, true, true],
+            /* Fault     */ [true, false, false, false, false, false, false, true],
+        ];
+        for (i, from) in ALL_STATES.into_iter().enumerate() {
+            for (j, to) in ALL_STATES.into_iter().enumerate() {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    expected[i][j],
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn start_is_rejected_mid_stroke() {
+        assert!(!Bending.can_transition_to(Armed));
+        assert!(!Returning.can_transition_to(Bending));
+    }
+
+    #[test]
+    fn pause_is_not_taken_mid_bend() {
+        assert!(!Bending.can_transition_to(Paused));
+        assert!(!Paused.is_running(), "A paused job is not supervised for stalls");
+    }
+
+    #[test]
+    fn start_only_from_rest_top_paused_or_safe_stop() {
+        for state in ALL_STATES {
+            assert_eq!(state.can_start(), matches!(state, Idle | Armed | Paused | SafeStop), "{:?}", state);
+            if state.can_start() {
+                assert!(state.can_transition_to(Armed));
+            }
+        }
+    }
+
+    #[test]
+    fn manual_override_is_rejected_while_running() {
+        for from in [Armed, Bending, Returning, Paused, SafeStop, Fault] {
+            assert!(!from.can_transition_to(Manual), "{:?} -> Manual", from);
+        }
+    }
+}
@@ -1,7 +1,29 @@
+mod alarm;
+mod calibration;
+mod command;
+mod control;
+mod events;
+mod feedback;
+mod hysteresis;
+mod interlock;
+mod latency;
+mod machine;
+mod navigation;
+mod output;
+mod program;
+mod resolution;
+mod results;
 mod rotary;
+mod snapshot;
+mod teach;
+mod travel;
+mod velocity;
 mod webserver;
 
-use esp_idf_hal::gpio::{Gpio21, Gpio22, Gpio32, PinDriver, Pull};
+use alarm::AlarmCode;
+use command::CommandReceiver;
+use control::{ControlInputs, Controller};
+use esp_idf_hal::gpio::{Gpio21, Gpio22, Gpio25, Gpio26, Gpio27, Gpio32, PinDriver, Pull};
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
 use esp_idf_sys as _;
@@ -9,7 +31,7 @@ use log::*;
 use rotary::RotaryEncoderState;
 use rotary_encoder_embedded::{angular_velocity::AngularVelocityMode, Direction};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 fn main() -> anyhow::Result<()> {
     // Initialize ESP-IDF services
@@ -21,16 +43,27 @@ fn main() -> anyhow::Result<()> {
 
     let peripherals = Peripherals::take()?;
 
-    // Create rotary encoder state (0-720 steps, supports both Full (1°/step) and Half (0.5°/step) modes)
-    let encoder_state = RotaryEncoderState::new(0, 720);
+    // Create rotary encoder state (travel range and resolution come from the settings)
+    let encoder_state = RotaryEncoderState::new();
     let encoder_state_clone = encoder_state.clone();
+    let encoder_state_task = encoder_state.clone();
     let encoder_state_web = encoder_state.clone();
 
+    // Web handlers send their state changes to the rotary task over this channel
+    let (command_sender, command_receiver) = command::channel();
+
     // Set up GPIO pins for rotary encoder (CLK=21, DT=22)
     let clk_pin = peripherals.pins.gpio21;
     let dt_pin = peripherals.pins.gpio22;
     let output_pin = peripherals.pins.gpio32;
 
+    // Safety interlock inputs (guard door=25, light curtain=26)
+    let guard_door_pin = peripherals.pins.gpio25;
+    let light_curtain_pin = peripherals.pins.gpio26;
+
+    // Top-dead-centre reference input (27)
+    let reference_pin = peripherals.pins.gpio27;
+
     // Spawn rotary encoder task on Core 1 (dedicated for interrupts and encoder)
     info!("Starting rotary encoder task on Core 1...");
     ThreadSpawnConfiguration {
@@ -46,8 +79,22 @@ fn main() -> anyhow::Result<()> {
         .stack_size(8192)
         .name("rotary_core".to_string())
         .spawn(move || {
-            if let Err(e) = rotary_task(encoder_state_clone, clk_pin, dt_pin, output_pin) {
+            if let Err(e) = rotary_task(
+                encoder_state_clone,
+                command_receiver,
+                clk_pin,
+                dt_pin,
+                output_pin,
+                guard_door_pin,
+                light_curtain_pin,
+                reference_pin,
+            ) {
                 error!("Rotary task error: {:?}", e);
+                encoder_state_task.raise_alarm(
+                    AlarmCode::EncoderError,
+                    format!("Rotary task stopped: {}", e),
+                    encoder_state_task.uptime_ms(),
+                );
             }
         })?;
 
@@ -63,16 +110,20 @@ fn main() -> anyhow::Result<()> {
     .set()?;
 
     // Start webserver (blocks on this core)
-    webserver::start_webserver(encoder_state_web, peripherals.modem)?;
+    webserver::start_webserver(encoder_state_web, command_sender, peripherals.modem)?;
 
     Ok(())
 }
 
 fn rotary_task(
     encoder_state: RotaryEncoderState,
+    commands: CommandReceiver,
     clk_pin: Gpio21,
     dt_pin: Gpio22,
     output_pin: Gpio32,
+    guard_door_pin: Gpio25,
+    light_curtain_pin: Gpio26,
+    reference_pin: Gpio27,
 ) -> anyhow::Result<()> {
     info!("Rotary encoder task running on Core 1");
 
@@ -94,10 +145,23 @@ fn rotary_task(
           if dt_initial { "HIGH" } else { "LOW" },
           if dt_initial { "1" } else { "0" });
 
+    // Set up interlock inputs; an unwired input reads HIGH (open) through the pull-up
+    let mut guard_door = PinDriver::input(guard_door_pin)?;
+    guard_door.set_pull(Pull::Up)?;
+
+    let mut light_curtain = PinDriver::input(light_curtain_pin)?;
+    light_curtain.set_pull(Pull::Up)?;
+
+    // Reference sensor output (NPN, open collector) pulls LOW at top dead centre
+    let mut reference = PinDriver::input(reference_pin)?;
+    reference.set_pull(Pull::Up)?;
+
     // Set up output pin
     let mut output = PinDriver::output(output_pin)?;
     output.set_low()?;
 
+    let mut controller = Controller::new(encoder_state.clone());
+
     // Initialize the rotary encoder using the library's AngularVelocityMode
     // This mode uses time-based velocity tracking for more accurate direction detection,
     // reducing the ±1° error observed with StandardMode when bending metal.
@@ -106,178 +170,39 @@ fn rotary_task(
     info!("✓ Using rotary-encoder-embedded library with AngularVelocityMode");
     info!("✓ Polling mode: Checking encoder state every 1ms (~1000Hz)");
 
-    // Record start time for velocity timestamping (AngularVelocityMode requires ms timestamps)
-    let start_time = Instant::now();
-
     // Main rotary encoder loop with polling
     loop {
+        // Apply web server commands at the start of the cycle, before the inputs are sampled
+        commands.drain(|command| controller.apply(command));
+
         // Poll the encoder pins at ~1000Hz (recommended by the library)
         // Read current pin states
         let clk_state = clk.is_high();
         let dt_state = dt.is_high();
         
-        // Get elapsed time in milliseconds for AngularVelocityMode
-        let current_time_millis = start_time.elapsed().as_millis() as u64;
+        // Get elapsed time in milliseconds for AngularVelocityMode. The same clock
+        // timestamps interlock and alarm events.
+        let current_time_millis = encoder_state.uptime_ms();
         
         // Update the encoder and get direction
-        let direction = rotary_encoder.update(dt_state, clk_state, current_time_millis);
-        
-        // Process direction changes
-        match direction {
-            Direction::Clockwise => {
-                encoder_state.update_from_direction(1);
-            }
-            Direction::Anticlockwise => {
-                encoder_state.update_from_direction(-1);
-            }
-            Direction::None => {
-                // No change
-            }
-        }
-        
-        // Handle target angle logic - safe stop has the highest priority
-        if encoder_state.is_safe_stop_active() {
-            let angle = encoder_state.get_angle();
-            let settings = encoder_state.get_settings();
-            if angle < settings.minimum_angle_threshold {
-                // Angle has dropped below the minimum threshold - safe stop complete
-                output.set_low()?;
-                encoder_state.output_on.store(false, std::sync::atomic::Ordering::SeqCst);
-                encoder_state.complete_safe_stop();
-                info!("✅ Safe stop complete - output OFF, angle reset to 0°");
-            } else {
-                // Keep output ON to trigger machine reversal
-                output.set_high()?;
-                encoder_state.output_on.store(true, std::sync::atomic::Ordering::SeqCst);
-            }
-        } else if encoder_state.is_active() {
-            let targets = encoder_state.target_angles.lock()
-                .expect("Target angles mutex poisoned");
-            
-            if !targets.is_empty() {
-                let current_idx = *encoder_state.current_target_index.lock()
-                    .expect("Current target index mutex poisoned");
-                
-                if current_idx < targets.len() {
-                    let target = targets[current_idx];
-                    drop(targets);
-
-                    let steps = encoder_state.get_value();
-                    let angle = encoder_state.get_angle();
-                    let settings = encoder_state.get_settings();
-                    let divisor = match settings.step_mode {
-                        crate::rotary::StepMode::Full => 1.0,
-                        crate::rotary::StepMode::Half => 2.0,
-                    };
-                    let target_angle = target as f32 / divisor;
-
-                    // Check for manual output override
-                    if encoder_state.is_manual_output_override() {
-                        // Manual control is active, don't interfere
-                        let manual_state = encoder_state.get_manual_output_state();
-                        if manual_state {
-                            output.set_high()?;
-                        } else {
-                            output.set_low()?;
-                        }
-                        encoder_state.output_on.store(manual_state, std::sync::atomic::Ordering::SeqCst);
-                    } else {
-                        // Automatic output control based on target
-                        // Trigger output when reaching target (moving forward from 0)
-                        if !encoder_state.triggered.load(std::sync::atomic::Ordering::SeqCst) 
-                            && steps >= target {
-                            output.set_high()?;
-                            encoder_state.output_on.store(true, std::sync::atomic::Ordering::SeqCst);
-                            encoder_state.triggered.store(true, std::sync::atomic::Ordering::SeqCst);
-                            info!("⚡ Target reached: {:.1}°", target_angle);
-                        } else if encoder_state.triggered.load(std::sync::atomic::Ordering::SeqCst) {
-                            // Target was reached, now manage output based on settings
-                            let hold_until_threshold = settings.hold_output_until_threshold;
-                            
-                            if hold_until_threshold {
-                                // Keep output on until angle drops below threshold
-                                if angle < settings.minimum_angle_threshold {
-                                    output.set_low()?;
-                                    encoder_state.output_on.store(false, std::sync::atomic::Ordering::SeqCst);
-                                }
-                            } else {
-                                // Turn off output as soon as we go below target
-                                if steps < target {
-                                    output.set_low()?;
-                                    encoder_state.output_on.store(false, std::sync::atomic::Ordering::SeqCst);
-                                }
-                            }
-                        } else {
-                            output.set_low()?;
-                            encoder_state.output_on.store(false, std::sync::atomic::Ordering::SeqCst);
-                        }
-                    }
-
-                    // Reset encoder if angle drops below threshold AND target was already triggered
-                    if encoder_state.triggered.load(std::sync::atomic::Ordering::SeqCst)
-                        && angle < settings.minimum_angle_threshold 
-                        && !encoder_state.reset_detected.load(std::sync::atomic::Ordering::SeqCst) {
-                        encoder_state.set_value(0);
-                        encoder_state.reset_detected.store(true, std::sync::atomic::Ordering::SeqCst);
-                        encoder_state.triggered.store(false, std::sync::atomic::Ordering::SeqCst);
-                        // Clear manual override on reset
-                        encoder_state.clear_manual_output();
-                        info!("🔄 Encoder reset to 0°");
-
-                        // Advance to next target
-                        let mut idx = encoder_state.current_target_index.lock()
-                            .expect("Current target index mutex poisoned");
-                        *idx += 1;
-                        let new_idx = *idx;
-                        drop(idx);
-
-                        let targets = encoder_state.target_angles.lock()
-                            .expect("Target angles mutex poisoned");
-                        if new_idx >= targets.len() {
-                            // All targets for this run completed
-                            let current_run = encoder_state.get_current_run();
-                            let total_runs = encoder_state.get_total_runs();
-                            info!("✅ Run {}/{} completed and returned to 0°.", current_run, total_runs);
-                            
-                            if current_run < total_runs {
-                                // Start next run
-                                encoder_state.increment_current_run();
-                                *encoder_state.current_target_index.lock()
-                                    .expect("Current target index mutex poisoned") = 0;
-                                info!("🔄 Starting run {}/{}...", encoder_state.get_current_run(), total_runs);
-                            } else {
-                                // All runs completed - initiate safe stop
-                                info!("✅ All {} runs completed!", total_runs);
-                                encoder_state.stop();
-                            }
-                        }
-                        drop(targets);
-                    }
-
-                    if angle > 5.0 {
-                        encoder_state.reset_detected.store(false, std::sync::atomic::Ordering::SeqCst);
-                    }
-                } else {
-                    drop(targets);
-                }
-            } else {
-                drop(targets);
-            }
+        let direction = match rotary_encoder.update(dt_state, clk_state, current_time_millis) {
+            Direction::Clockwise => 1,
+            Direction::Anticlockwise => -1,
+            Direction::None => 0,
+        };
+
+        // Interlock contacts are normally closed to GND: HIGH means open
+        let inputs = ControlInputs {
+            direction,
+            guard_door_open: guard_door.is_high(),
+            light_curtain_open: light_curtain.is_high(),
+            reference_active: reference.is_low(),
+        };
+
+        if controller.step(inputs, current_time_millis) {
+            output.set_high()?;
         } else {
-            // When encoder is not active, check for manual output override
-            if encoder_state.is_manual_output_override() {
-                let manual_state = encoder_state.get_manual_output_state();
-                if manual_state {
-                    output.set_high()?;
-                } else {
-                    output.set_low()?;
-                }
-                encoder_state.output_on.store(manual_state, std::sync::atomic::Ordering::SeqCst);
-            } else {
-                // Encoder not active and no manual override - ensure output pin is off
-                output.set_low()?;
-                encoder_state.output_on.store(false, std::sync::atomic::Ordering::SeqCst);
-            }
+            output.set_low()?;
         }
         
         // Poll at ~1000Hz (1ms delay) as recommended by the library
@@ -0,0 +1,138 @@
+use crate::machine::MachineState;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Operator request to move within the running program.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum StepCommand {
+    /// Do the bend that was just completed again
+    Repeat,
+    /// Leave out the next bend
+    Skip,
+    /// Move back one bend
+    Back,
+    /// Go to a bend (1-based), in the given run or the current one
+    Jump { bend: usize, run: Option<i32> },
+}
+
+/// Next bend to be done: `index` is 0-based, `run` is 1-based like `current_run`.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct StepPosition {
+    pub index: usize,
+    pub run: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NavigationError {
+    /// Only allowed with the ram at the top of the stroke (Armed or Paused)
+    NotAtTop(MachineState),
+    /// No bend has been completed in this job yet
+    NothingToRepeat,
+    StartOfProgram,
+    /// Skipping the last bend would end the job; use Stop instead
+    EndOfProgram,
+    BendOutOfRange { bend: usize, bends: usize },
+    RunOutOfRange { run: i32, total_runs: i32 },
+}
+
+impl fmt::Display for NavigationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NavigationError::NotAtTop(state) => write!(f, "Ram must be at the top of the stroke (machine is {:?})", state),
+            NavigationError::NothingToRepeat => write!(f, "No bend completed yet"),
+            NavigationError::StartOfProgram => write!(f, "Already at the first bend of the first run"),
+            NavigationError::EndOfProgram => write!(f, "Cannot skip the last bend of the last run"),
+            NavigationError::BendOutOfRange { bend, bends } => write!(f, "Bend {} is not in 1..={}", bend, bends),
+            NavigationError::RunOutOfRange { run, total_runs } => write!(f, "Run {} is not in 1..={}", run, total_runs),
+        }
+    }
+}
+
+/// Work out where `step` moves the program to. `bends` is the number of bends
+/// per run and must be at least 1.
+pub fn navigate(
+    step: StepCommand,
+    position: StepPosition,
+    last_completed: Option<StepPosition>,
+    bends: usize,
+    total_runs: i32,
+) -> Result<StepPosition, NavigationError> {
+    match step {
+        StepCommand::Repeat => last_completed.ok_or(NavigationError::NothingToRepeat),
+        StepCommand::Skip => {
+            if position.index + 1 < bends {
+                Ok(StepPosition { index: position.index + 1, ..position })
+            } else if position.run < total_runs {
+                Ok(StepPosition { index: 0, run: position.run + 1 })
+            } else {
+                Err(NavigationError::EndOfProgram)
+            }
+        }
+        StepCommand::Back => {
+            if position.index > 0 {
+                Ok(StepPosition { index: position.index - 1, ..position })
+            } else if position.run > 1 {
+                Ok(StepPosition { index: bends - 1, run: position.run - 1 })
+            } else {
+                Err(NavigationError::StartOfProgram)
+            }
+        }
+        StepCommand::Jump { bend, run } => {
+            if bend == 0 || bend > bends {
+                return Err(NavigationError::BendOutOfRange { bend, bends });
+            }
+            let run = run.unwrap_or(position.run);
+            if run < 1 || run > total_runs {
+                return Err(NavigationError::RunOutOfRange { run, total_runs });
+            }
+            Ok(StepPosition { index: bend - 1, run })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(index: usize, run: i32) -> StepPosition {
+        StepPosition { index, run }
+    }
+
+    #[test]
+    fn skip_and_back_cross_run_boundaries() {
+        assert_eq!(navigate(StepCommand::Skip, at(2, 1), None, 3, 2), Ok(at(0, 2)));
+        assert_eq!(navigate(StepCommand::Back, at(0, 2), None, 3, 2), Ok(at(2, 1)));
+        assert_eq!(navigate(StepCommand::Back, at(1, 1), None, 3, 2), Ok(at(0, 1)));
+    }
+
+    #[test]
+    fn program_ends_are_rejected() {
+        assert_eq!(navigate(StepCommand::Back, at(0, 1), None, 3, 2), Err(NavigationError::StartOfProgram));
+        assert_eq!(navigate(StepCommand::Skip, at(2, 2), None, 3, 2), Err(NavigationError::EndOfProgram));
+    }
+
+    #[test]
+    fn repeat_goes_to_last_completed_bend() {
+        assert_eq!(navigate(StepCommand::Repeat, at(0, 2), Some(at(2, 1)), 3, 2), Ok(at(2, 1)));
+        assert_eq!(navigate(StepCommand::Repeat, at(0, 1), None, 3, 2), Err(NavigationError::NothingToRepeat));
+    }
+
+    #[test]
+    fn jump_is_one_based_and_range_checked() {
+        let jump = |bend, run| StepCommand::Jump { bend, run };
+        assert_eq!(navigate(jump(1, None), at(2, 2), None, 3, 2), Ok(at(0, 2)));
+        assert_eq!(navigate(jump(3, Some(1)), at(0, 2), None, 3, 2), Ok(at(2, 1)));
+        assert_eq!(navigate(jump(0, None), at(0, 1), None, 3, 2), Err(NavigationError::BendOutOfRange { bend: 0, bends: 3 }));
+        assert_eq!(navigate(jump(4, None), at(0, 1), None, 3, 2), Err(NavigationError::BendOutOfRange { bend: 4, bends: 3 }));
+        assert_eq!(navigate(jump(1, Some(3)), at(0, 1), None, 3, 2), Err(NavigationError::RunOutOfRange { run: 3, total_runs: 2 }));
+    }
+
+    #[test]
+    fn step_command_json() {
+        let parse = |json| serde_json::from_str::<StepCommand>(json).unwrap();
+        assert_eq!(parse(r#"{"action":"skip"}"#), StepCommand::Skip);
+        assert_eq!(parse(r#"{"action":"jump","bend":2}"#), StepCommand::Jump { bend: 2, run: None });
+        assert_eq!(parse(r#"{"action":"jump","bend":2,"run":3}"#), StepCommand::Jump { bend: 2, run: Some(3) });
+    }
+}
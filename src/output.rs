@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// How the output behaves once a bend's trigger point is reached.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OutputMode {
+    /// On at the trigger point, off at the release point (or at the minimum
+    /// angle threshold with `hold_output_until_threshold`)
+    #[default]
+    Level,
+    /// On at the trigger point for a fixed time, whatever the ram does
+    Pulse { width_ms: u32 },
+    /// Wait at the target for `dwell_ms`, then switch on like `Level`
+    Dwell { dwell_ms: u32 },
+    /// Flip the output at every trigger and keep it until the next one
+    Toggle,
+}
+
+impl OutputMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            OutputMode::Pulse { width_ms: 0 } => Err("Pulse width must be at least 1 ms".to_string()),
+            OutputMode::Dwell { dwell_ms: 0 } => Err("Dwell time must be at least 1 ms".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_mode_json() {
+        let parse = |json| serde_json::from_str::<OutputMode>(json).unwrap();
+        assert_eq!(parse(r#"{"mode":"level"}"#), OutputMode::Level);
+        assert_eq!(parse(r#"{"mode":"pulse","width_ms":150}"#), OutputMode::Pulse { width_ms: 150 });
+        assert_eq!(parse(r#"{"mode":"dwell","dwell_ms":500}"#), OutputMode::Dwell { dwell_ms: 500 });
+        assert_eq!(parse(r#"{"mode":"toggle"}"#), OutputMode::Toggle);
+    }
+
+    #[test]
+    fn zero_length_modes_are_rejected() {
+        assert!(OutputMode::Pulse { width_ms: 0 }.validate().is_err());
+        assert!(OutputMode::Dwell { dwell_ms: 0 }.validate().is_err());
+        assert!(OutputMode::Pulse { width_ms: 1 }.validate().is_ok());
+        assert!(OutputMode::Toggle.validate().is_ok());
+    }
+}
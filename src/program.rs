@@ -0,0 +1,224 @@
+use crate::rotary::BendOptions;
+use crate::travel::OutOfTravel;
+use serde::Deserialize;
+use std::fmt;
+
+/// Change to a bend that has not been executed yet. `bend` is the 1-based
+/// position in the program.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ProgramEdit {
+    /// Change the target angle of a bend
+    Set { bend: usize, angle: f32 },
+    /// Insert a bend before `bend`; `bend` one past the end appends
+    Insert {
+        bend: usize,
+        angle: f32,
+        #[serde(default)]
+        options: BendOptions,
+    },
+    Delete { bend: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditError {
+    /// No job is running
+    NoJob,
+    /// The bend is done or in progress in the current run
+    AlreadyExecuted { bend: usize, first_editable: usize },
+    OutOfRange { bend: usize, bends: usize },
+    /// Deleting would remove the bend the ram is waiting to do next, and it is the last one
+    NoBendLeft,
+    OutOfTravel(OutOfTravel),
+    /// An inserted bend's pulse width or dwell time is 0
+    InvalidOutputMode { bend: usize },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::NoJob => write!(f, "No job running"),
+            EditError::AlreadyExecuted { bend, first_editable } => write!(
+                f,
+                "Bend {} is already done or in progress; only bends from {} on can be changed",
+                bend, first_editable
+            ),
+            EditError::OutOfRange { bend, bends } => write!(f, "Bend {} is not in the program ({} bends)", bend, bends),
+            EditError::NoBendLeft => write!(f, "Cannot delete the next bend when it is the last one"),
+            EditError::OutOfTravel(e) => write!(f, "{}", e),
+            EditError::InvalidOutputMode { bend } => {
+                write!(f, "Bend {}: pulse width and dwell time must be at least 1 ms", bend)
+            }
+        }
+    }
+}
+
+/// Apply `edit` to a program. Bends before `first_editable` (0-based) are left
+/// alone, and `current` (the bend index of the current position) must still be
+/// in the program afterwards. `to_steps` converts degrees into encoder steps
+/// and rejects angles outside the travel range; like at Start, the angle with
+/// the bend's compensation added must be within the range too.
+pub fn apply_edit(
+    targets: &mut Vec<i32>,
+    options: &mut Vec<BendOptions>,
+    edit: ProgramEdit,
+    current: usize,
+    first_editable: usize,
+    to_steps: impl Fn(f32) -> Result<i32, OutOfTravel>,
+) -> Result<(), EditError> {
+    let bends = targets.len();
+    let (bend, last) = match edit {
+        ProgramEdit::Insert { bend, .. } => (bend, bends + 1),
+        ProgramEdit::Set { bend, .. } | ProgramEdit::Delete { bend } => (bend, bends),
+    };
+    if bend == 0 || bend > last {
+        return Err(EditError::OutOfRange { bend, bends });
+    }
+    let index = bend - 1;
+    if index < first_editable {
+        return Err(EditError::AlreadyExecuted { bend, first_editable: first_editable + 1 });
+    }
+
+    let compensated_steps = |angle: f32, compensation: f32| {
+        to_steps(angle + compensation)
+            .and_then(|_| to_steps(angle))
+            .map_err(EditError::OutOfTravel)
+    };
+    match edit {
+        ProgramEdit::Set { angle, .. } => targets[index] = compensated_steps(angle, options[index].compensation)?,
+        ProgramEdit::Insert { angle, options: bend_options, .. } => {
+            bend_options.validate().map_err(|_| EditError::InvalidOutputMode { bend })?;
+            targets.insert(index, compensated_steps(angle, bend_options.compensation)?);
+            options.insert(index, bend_options);
+        }
+        ProgramEdit::Delete { .. } => {
+            if bends - 1 <= current {
+                return Err(EditError::NoBendLeft);
+            }
+            targets.remove(index);
+            options.remove(index);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputMode;
+
+    fn program(targets: &[i32]) -> (Vec<i32>, Vec<BendOptions>) {
+        (targets.to_vec(), vec![BendOptions::default(); targets.len()])
+    }
+
+    // Ram at the top, waiting to do bend `current`
+    fn edit(targets: &mut Vec<i32>, options: &mut Vec<BendOptions>, edit: ProgramEdit, current: usize) -> Result<(), EditError> {
+        apply_edit(targets, options, edit, current, current, |angle| Ok(angle as i32))
+    }
+
+    #[test]
+    fn set_insert_and_delete_upcoming_bends() {
+        let (mut targets, mut options) = program(&[10, 20, 30]);
+        edit(&mut targets, &mut options, ProgramEdit::Set { bend: 2, angle: 25.0 }, 1).unwrap();
+        assert_eq!(targets, vec![10, 25, 30]);
+        let insert = ProgramEdit::Insert { bend: 4, angle: 40.0, options: BendOptions::default() };
+        edit(&mut targets, &mut options, insert, 1).unwrap();
+        assert_eq!(targets, vec![10, 25, 30, 40]);
+        edit(&mut targets, &mut options, ProgramEdit::Delete { bend: 3 }, 1).unwrap();
+        assert_eq!(targets, vec![10, 25, 40]);
+        assert_eq!(options.len(), targets.len());
+    }
+
+    #[test]
+    fn executed_bends_are_rejected() {
+        let (mut targets, mut options) = program(&[10, 20, 30]);
+        assert_eq!(
+            edit(&mut targets, &mut options, ProgramEdit::Set { bend: 1, angle: 5.0 }, 1),
+            Err(EditError::AlreadyExecuted { bend: 1, first_editable: 2 })
+        );
+        assert_eq!(targets, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn out_of_range_bends_are_rejected() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        assert_eq!(
+            edit(&mut targets, &mut options, ProgramEdit::Delete { bend: 3 }, 0),
+            Err(EditError::OutOfRange { bend: 3, bends: 2 })
+        );
+        assert_eq!(
+            edit(&mut targets, &mut options, ProgramEdit::Set { bend: 0, angle: 5.0 }, 0),
+            Err(EditError::OutOfRange { bend: 0, bends: 2 })
+        );
+    }
+
+    #[test]
+    fn next_bend_cannot_be_deleted_when_last() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        assert_eq!(edit(&mut targets, &mut options, ProgramEdit::Delete { bend: 2 }, 1), Err(EditError::NoBendLeft));
+        edit(&mut targets, &mut options, ProgramEdit::Delete { bend: 2 }, 0).unwrap();
+        assert_eq!(targets, vec![10]);
+    }
+
+    #[test]
+    fn last_bend_can_be_deleted_while_previous_is_in_progress() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        apply_edit(&mut targets, &mut options, ProgramEdit::Delete { bend: 2 }, 0, 1, |angle| Ok(angle as i32)).unwrap();
+        assert_eq!(targets, vec![10]);
+    }
+
+    #[test]
+    fn angles_outside_travel_are_rejected() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        let error = OutOfTravel { angle: 400.0, min_angle: 0.0, max_angle: 360.0 };
+        let result = apply_edit(&mut targets, &mut options, ProgramEdit::Set { bend: 2, angle: 400.0 }, 0, 0, |_| Err(error));
+        assert_eq!(result, Err(EditError::OutOfTravel(error)));
+        assert_eq!(targets, vec![10, 20]);
+    }
+
+    #[test]
+    fn inserted_bend_options_are_validated() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        let bend_options = BendOptions { output_mode: Some(OutputMode::Pulse { width_ms: 0 }), ..Default::default() };
+        let insert = ProgramEdit::Insert { bend: 2, angle: 15.0, options: bend_options };
+        assert_eq!(edit(&mut targets, &mut options, insert, 0), Err(EditError::InvalidOutputMode { bend: 2 }));
+        assert_eq!(targets, vec![10, 20]);
+        assert_eq!(options.len(), 2);
+    }
+
+    #[test]
+    fn compensated_angle_must_be_within_travel() {
+        let (mut targets, mut options) = program(&[10, 20]);
+        options[1].compensation = 5.0;
+        let to_steps = |angle: f32| {
+            if angle > 100.0 {
+                Err(OutOfTravel { angle, min_angle: 0.0, max_angle: 100.0 })
+            } else {
+                Ok(angle as i32)
+            }
+        };
+        let set = ProgramEdit::Set { bend: 2, angle: 98.0 };
+        let result = apply_edit(&mut targets, &mut options, set, 0, 0, to_steps);
+        assert!(matches!(result, Err(EditError::OutOfTravel(OutOfTravel { angle, .. })) if angle == 103.0));
+        let bend_options = BendOptions { compensation: -5.0, ..Default::default() };
+        let insert = ProgramEdit::Insert { bend: 1, angle: 3.0, options: bend_options };
+        let result = apply_edit(&mut targets, &mut options, insert, 0, 0, |angle| {
+            if angle < 0.0 { Err(OutOfTravel { angle, min_angle: 0.0, max_angle: 100.0 }) } else { Ok(angle as i32) }
+        });
+        assert!(matches!(result, Err(EditError::OutOfTravel(_))));
+        assert_eq!(targets, vec![10, 20]);
+        apply_edit(&mut targets, &mut options, ProgramEdit::Set { bend: 2, angle: 95.0 }, 0, 0, to_steps).unwrap();
+        assert_eq!(targets, vec![10, 95]);
+    }
+
+    #[test]
+    fn program_edit_json() {
+        let parse = |json| serde_json::from_str::<ProgramEdit>(json).unwrap();
+        assert_eq!(parse(r#"{"action":"set","bend":2,"angle":45.5}"#), ProgramEdit::Set { bend: 2, angle: 45.5 });
+        assert_eq!(parse(r#"{"action":"delete","bend":1}"#), ProgramEdit::Delete { bend: 1 });
+        assert_eq!(
+            parse(r#"{"action":"insert","bend":1,"angle":30}"#),
+            ProgramEdit::Insert { bend: 1, angle: 30.0, options: BendOptions::default() }
+        );
+    }
+}
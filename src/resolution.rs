@@ -0,0 +1,93 @@
+use crate::rotary::StepMode;
+use serde::{Deserialize, Serialize};
+
+/// Encoder and drive geometry: how many counts make one degree of the beam.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EncoderResolution {
+    /// Encoder pulses per revolution, per channel
+    pub pulses_per_revolution: u32,
+    /// Counts per pulse from the quadrature decoding (1, 2 or 4)
+    pub quadrature_multiplier: u8,
+    /// Encoder revolutions per revolution of the beam
+    pub gear_ratio: f32,
+}
+
+impl Default for EncoderResolution {
+    /// One count per degree, the old Full step mode
+    fn default() -> Self {
+        Self {
+            pulses_per_revolution: 360,
+            quadrature_multiplier: 1,
+            gear_ratio: 1.0,
+        }
+    }
+}
+
+impl From<StepMode> for EncoderResolution {
+    fn from(mode: StepMode) -> Self {
+        let quadrature_multiplier = match mode {
+            StepMode::Full => 1,
+            StepMode::Half => 2,
+        };
+        Self { quadrature_multiplier, ..Self::default() }
+    }
+}
+
+impl EncoderResolution {
+    pub fn counts_per_degree(&self) -> f32 {
+        self.pulses_per_revolution as f32 * self.quadrature_multiplier as f32 * self.gear_ratio / 360.0
+    }
+
+    pub fn steps_to_degrees(&self, steps: i32) -> f32 {
+        steps as f32 / self.counts_per_degree()
+    }
+
+    /// Rounded to the nearest count
+    pub fn degrees_to_steps(&self, angle: f32) -> i32 {
+        (angle * self.counts_per_degree()).round() as i32
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pulses_per_revolution == 0 {
+            return Err("Pulses per revolution must be at least 1".to_string());
+        }
+        if !matches!(self.quadrature_multiplier, 1 | 2 | 4) {
+            return Err(format!("Quadrature multiplier must be 1, 2 or 4, not {}", self.quadrature_multiplier));
+        }
+        if !(self.gear_ratio.is_finite() && self.gear_ratio > 0.0) {
+            return Err(format!("Gear ratio must be greater than 0, not {}", self.gear_ratio));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_modes_keep_their_resolution() {
+        assert_eq!(EncoderResolution::from(StepMode::Full).counts_per_degree(), 1.0);
+        assert_eq!(EncoderResolution::from(StepMode::Half).counts_per_degree(), 2.0);
+        assert_eq!(EncoderResolution::default(), EncoderResolution::from(StepMode::Full));
+    }
+
+    #[test]
+    fn gear_ratio_and_quadrature_scale_counts() {
+        let resolution = EncoderResolution { pulses_per_revolution: 600, quadrature_multiplier: 4, gear_ratio: 1.5 };
+        assert_eq!(resolution.counts_per_degree(), 10.0);
+        assert_eq!(resolution.degrees_to_steps(90.05), 901);
+        assert_eq!(resolution.steps_to_degrees(905), 90.5);
+    }
+
+    #[test]
+    fn invalid_resolution_is_rejected() {
+        let valid = EncoderResolution::default();
+        assert!(valid.validate().is_ok());
+        assert!(EncoderResolution { pulses_per_revolution: 0, ..valid }.validate().is_err());
+        assert!(EncoderResolution { quadrature_multiplier: 3, ..valid }.validate().is_err());
+        assert!(EncoderResolution { gear_ratio: 0.0, ..valid }.validate().is_err());
+        assert!(EncoderResolution { gear_ratio: f32::NAN, ..valid }.validate().is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+
+// Bends kept for the API; older ones are dropped on long jobs
+const RESULTS_LEN: usize = 256;
+
+/// What the ram did on one completed bend.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub struct BendResult {
+    /// 1-based run and bend number
+    pub run: i32,
+    pub bend: usize,
+    pub target_angle: f32,
+    /// Springback compensation the bend was made with; the ram was driven to
+    /// `target_angle + compensation`
+    pub compensation: f32,
+    /// Highest angle reached between the trigger and the return to the top
+    pub peak_angle: f32,
+    /// From the ram leaving the top to the trigger point
+    pub time_to_target_ms: u32,
+    /// From the trigger point back to the minimum angle threshold
+    pub return_time_ms: u32,
+    /// The peak angle is further from the compensated target than the bend's accept tolerance
+    pub rejected: bool,
+    /// Program the bend index refers to; feedback is refused once it changes
+    #[serde(skip)]
+    pub program_version: u32,
+}
+
+/// Results of the current job as served by `/api/results`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResultsReport {
+    pub results: Vec<BendResult>,
+    /// Older results of this job no longer kept
+    pub dropped: u32,
+    /// Rejected bends in this job, including dropped ones
+    pub rejects: u32,
+}
+
+/// Results of the current job, oldest first. Cleared on Start.
+#[derive(Debug, Default)]
+pub struct JobResults {
+    results: VecDeque<BendResult>,
+    dropped: u32,
+    rejects: u32,
+    last_reject: Option<BendResult>,
+}
+
+impl JobResults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, result: BendResult) {
+        if self.results.len() == RESULTS_LEN {
+            self.results.pop_front();
+            self.dropped += 1;
+        }
+        self.results.push_back(result);
+        if result.rejected {
+            self.rejects += 1;
+            self.last_reject = Some(result);
+        }
+    }
+
+    pub fn report(&self) -> ResultsReport {
+        ResultsReport {
+            results: self.results.iter().copied().collect(),
+            dropped: self.dropped,
+            rejects: self.rejects,
+        }
+    }
+
+    pub fn last(&self) -> Option<BendResult> {
+        self.results.back().copied()
+    }
+
+    /// Point the last result at a program version that only differs in
+    /// compensation, so the bend can be measured again
+    pub fn set_last_program_version(&mut self, program_version: u32) {
+        if let Some(last) = self.results.back_mut() {
+            last.program_version = program_version;
+        }
+    }
+
+    pub fn rejects(&self) -> u32 {
+        self.rejects
+    }
+
+    pub fn last_reject(&self) -> Option<BendResult> {
+        self.last_reject
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(bend: usize, rejected: bool) -> BendResult {
+        BendResult {
+            run: 1,
+            bend,
+            target_angle: 45.0,
+            compensation: 0.0,
+            peak_angle: 45.5,
+            time_to_target_ms: 800,
+            return_time_ms: 600,
+            rejected,
+            program_version: 1,
+        }
+    }
+
+    #[test]
+    fn oldest_results_are_dropped() {
+        let mut results = JobResults::new();
+        for bend in 1..=RESULTS_LEN + 2 {
+            results.push(result(bend, false));
+        }
+        let report = results.report();
+        assert_eq!(report.results.len(), RESULTS_LEN);
+        assert_eq!(report.results[0].bend, 3);
+        assert_eq!(report.dropped, 2);
+
+        results.clear();
+        assert!(results.report().results.is_empty());
+        assert_eq!(results.report().dropped, 0);
+    }
+
+    #[test]
+    fn rejects_are_counted_past_dropped_results() {
+        let mut results = JobResults::new();
+        results.push(result(1, true));
+        for bend in 2..=RESULTS_LEN + 1 {
+            results.push(result(bend, false));
+        }
+        results.push(result(RESULTS_LEN + 2, true));
+        assert_eq!(results.rejects(), 2);
+        assert_eq!(results.last_reject().map(|r| r.bend), Some(RESULTS_LEN + 2));
+        assert_eq!(results.report().results.iter().filter(|r| r.rejected).count(), 1);
+
+        results.clear();
+        assert_eq!((results.rejects(), results.last_reject()), (0, None));
+    }
+}
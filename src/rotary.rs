@@ -1,20 +1,78 @@
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use std::time::Instant;
 use serde::{Deserialize, Serialize};
-
+use crate::alarm::{AlarmCode, AlarmError, AlarmManager, AlarmStatus, Severity};
+use crate::calibration::Calibration;
+use crate::events::{Event, EventKind, EventLog};
+use crate::feedback::{FeedbackError, FeedbackLog, FeedbackRecord};
+use crate::hysteresis::{Hysteresis, HysteresisOverride};
+use crate::interlock::{InterlockInput, InterlockMonitor, InterlockStatus};
+use crate::machine::{IllegalTransition, MachineState};
+use crate::output::OutputMode;
+use crate::program::{self, EditError, ProgramEdit};
+use crate::resolution::EncoderResolution;
+use crate::results::{BendResult, JobResults, ResultsReport};
+use crate::snapshot::{ControlSnapshot, SnapshotCell};
+use crate::travel::{OutOfTravel, StepRange, Travel};
+
+// Missing fields fall back to their defaults so settings stored in NVS by an
+// older firmware still load after new fields are added.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub forward_direction: ForwardDirection,
-    pub step_mode: StepMode,
+    /// Encoder resolution and gearing used to convert counts to degrees
+    pub resolution: EncoderResolution,
+    /// Travel range; targets outside it are rejected
+    pub travel: Travel,
+    /// Written by older firmware and replaced by `resolution`; only read to
+    /// migrate stored settings (see `Settings::from_json`)
+    #[serde(skip_serializing)]
+    pub step_mode: Option<StepMode>,
     pub output_pin: u8,
     pub output_default_state: PinState,
+    /// Output behaviour at the target for bends that do not set their own
+    pub output_mode: OutputMode,
     pub minimum_angle_threshold: f32,
+    /// Trigger, release and re-arm thresholds around each bend
+    pub hysteresis: Hysteresis,
     pub hold_output_until_threshold: bool,
+    /// Valve and relay response time; the output fires this much early at the
+    /// current ram speed (0 disables)
+    pub output_latency_ms: u32,
     pub debug_enabled: bool,
     pub num_target_angles: u8,
     pub tick_size_multiplier: f32,
     pub number_of_runs: u32,
     pub update_rate_ms: u32,
+    pub guard_door_interlock_enabled: bool,
+    pub light_curtain_interlock_enabled: bool,
+    pub overtravel_tolerance: f32,
+    pub overtravel_safe_stop: bool,
+    /// A bend whose peak angle is further than this from the target is rejected (0 disables)
+    pub accept_tolerance: f32,
+    /// Raise a `Reject` alarm for a rejected bend
+    pub reject_alarm: bool,
+    /// Pause the job at the top of the stroke after a rejected bend
+    pub reject_pause: bool,
+    /// No movement mid-bend for this long raises a stall warning (0 disables)
+    pub stall_timeout_ms: u32,
+    /// No movement for this long ends the running job (0 disables)
+    pub idle_timeout_ms: u32,
+    /// Safe stop gives up and raises a fault after this long (0 disables)
+    pub safe_stop_timeout_ms: u32,
+    /// Manual output override is cleared unless renewed within this time (0 latches it)
+    pub manual_keepalive_timeout_ms: u32,
+    /// Manual output is switched off and the override cleared after being on this long (0 disables)
+    pub manual_max_on_ms: u32,
+    /// Angle of the zero reference position; zeroing loads this angle into the count
+    pub zero_offset: f32,
+    /// Re-zero the count from the top-dead-centre input on every stroke
+    pub reference_input_enabled: bool,
+    /// Two-point calibration; replaces the resolution conversion when set
+    pub calibration: Option<Calibration>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
@@ -23,6 +81,17 @@ pub enum ForwardDirection {
     CounterClockwise,
 }
 
+impl ForwardDirection {
+    /// Encoder direction (1 clockwise, -1 anticlockwise) as a change of the count
+    pub fn apply(self, direction: i32) -> i32 {
+        match self {
+            ForwardDirection::Clockwise => direction,
+            ForwardDirection::CounterClockwise => -direction,
+        }
+    }
+}
+
+/// Resolution setting of older firmware, see `EncoderResolution::from`
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub enum StepMode {
     Full,  // 1 degree per step
@@ -39,16 +108,169 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             forward_direction: ForwardDirection::Clockwise,
-            step_mode: StepMode::Full,
+            resolution: EncoderResolution::default(),
+            travel: Travel::default(),
+            step_mode: None,
             output_pin: 32,
             output_default_state: PinState::Low,
+            output_mode: OutputMode::Level,
             minimum_angle_threshold: 2.5,
+            hysteresis: Hysteresis::default(),
             hold_output_until_threshold: false,
+            output_latency_ms: 0,
             debug_enabled: false,
             num_target_angles: 1,
             tick_size_multiplier: 2.0,
             number_of_runs: 1,
             update_rate_ms: 200,
+            guard_door_interlock_enabled: false,
+            light_curtain_interlock_enabled: false,
+            overtravel_tolerance: 5.0,
+            overtravel_safe_stop: false,
+            accept_tolerance: 0.0,
+            reject_alarm: false,
+            reject_pause: false,
+            stall_timeout_ms: 3000,
+            idle_timeout_ms: 0,
+            safe_stop_timeout_ms: 10000,
+            manual_keepalive_timeout_ms: 2000,
+            manual_max_on_ms: 30000,
+            zero_offset: 0.0,
+            reference_input_enabled: false,
+            calibration: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Parse settings JSON, migrating fields written by older firmware.
+    pub fn from_json(json: &[u8]) -> serde_json::Result<Self> {
+        let mut settings: Settings = serde_json::from_slice(json)?;
+        if let Some(mode) = settings.step_mode.take() {
+            settings.resolution = EncoderResolution::from(mode);
+            log::info!("Migrated step mode {:?} to {:?}", mode, settings.resolution);
+        }
+        Ok(settings)
+    }
+
+    /// Checks for settings posted from the web page or loaded from NVS
+    pub fn validate(&self) -> Result<(), String> {
+        self.resolution.validate()?;
+        if let Some(calibration) = self.calibration {
+            calibration.validate()?;
+        }
+        self.travel.validate()?;
+        self.hysteresis.validate()?;
+        self.output_mode.validate()
+    }
+
+    /// Angle in degrees for an encoder count
+    pub fn steps_to_degrees(&self, steps: i32) -> f32 {
+        match self.calibration {
+            Some(calibration) => calibration.steps_to_degrees(steps),
+            None => self.resolution.steps_to_degrees(steps),
+        }
+    }
+
+    /// Encoder count for an angle, rounded to the nearest step
+    pub fn degrees_to_steps(&self, angle: f32) -> i32 {
+        match self.calibration {
+            Some(calibration) => calibration.degrees_to_steps(angle),
+            None => self.resolution.degrees_to_steps(angle),
+        }
+    }
+
+    /// Encoder count for a target angle, which must be within the travel range
+    pub fn target_steps(&self, angle: f32) -> Result<i32, OutOfTravel> {
+        self.travel.check(angle)?;
+        Ok(self.degrees_to_steps(angle))
+    }
+
+    /// Same conversion between counts and degrees, count direction and travel
+    /// range: the step targets of a loaded program keep their meaning
+    pub fn same_conversion(&self, other: &Settings) -> bool {
+        self.resolution == other.resolution
+            && self.calibration == other.calibration
+            && self.travel == other.travel
+            && self.forward_direction == other.forward_direction
+    }
+
+    /// Travel range in encoder counts
+    pub fn step_range(&self) -> StepRange {
+        StepRange {
+            min: self.degrees_to_steps(self.travel.min_angle),
+            max: self.degrees_to_steps(self.travel.max_angle),
+            wrap_around: self.travel.wrap_around,
+        }
+    }
+}
+
+/// Per-bend overrides sent with the target angles. Unset fields fall back to `Settings`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BendOptions {
+    /// Degrees past the target before an overtravel alarm is raised
+    pub overtravel_tolerance: Option<f32>,
+    /// Degrees either side of the target the peak angle may be off by
+    pub accept_tolerance: Option<f32>,
+    /// Added to the target angle to make up for springback; adjusted by operator feedback
+    pub compensation: f32,
+    pub hysteresis: HysteresisOverride,
+    pub output_mode: Option<OutputMode>,
+}
+
+impl BendOptions {
+    /// Checks for a bend entering a program, at Start or through an edit
+    pub fn validate(&self) -> Result<(), String> {
+        self.output_mode.map_or(Ok(()), |mode| mode.validate())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub struct OvertravelRecord {
+    pub run: i32,
+    pub bend_index: usize,
+    pub target_angle: f32,
+    pub peak_angle: f32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum SafeStopOutcome {
+    /// The ram returned below the minimum angle threshold
+    Completed,
+    /// The safe stop timeout expired first
+    TimedOut,
+    /// Ended early by an abort request, a new Start or a blocking alarm
+    Cancelled,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct SafeStopReport {
+    pub outcome: SafeStopOutcome,
+    pub duration_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StartError {
+    /// A blocking alarm is latched and has not been acknowledged yet
+    AlarmActive(AlarmCode),
+    /// The machine is in a state that cannot start a job (e.g. mid-stroke)
+    IllegalState(MachineState),
+    /// A target angle is outside the travel range; `bend` is 1-based
+    OutOfTravel { bend: usize, error: OutOfTravel },
+    /// A bend's pulse width or dwell time is 0; `bend` is 1-based
+    InvalidOutputMode { bend: usize },
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::AlarmActive(code) => write!(f, "{:?} alarm must be acknowledged before Start", code),
+            StartError::IllegalState(state) => write!(f, "Cannot start while {:?}", state),
+            StartError::OutOfTravel { bend, error } => write!(f, "Bend {}: {}", bend, error),
+            StartError::InvalidOutputMode { bend } => {
+                write!(f, "Bend {}: pulse width and dwell time must be at least 1 ms", bend)
+            }
         }
     }
 }
@@ -57,189 +279,351 @@ impl Default for Settings {
 pub struct RotaryEncoderState {
     pub value: Arc<AtomicI32>,
     pub target_angles: Arc<Mutex<Vec<i32>>>,
-    pub current_target_index: Arc<Mutex<usize>>,
-    pub encoder_active: Arc<AtomicBool>,
+    pub current_target_index: Arc<AtomicUsize>,
+    // Bumped after target_angles / bend_options are replaced
+    program_version: Arc<AtomicU32>,
+    pub bend_options: Arc<Mutex<Vec<BendOptions>>>,
+    pub last_overtravel: Arc<Mutex<Option<OvertravelRecord>>>,
+    machine_state: Arc<AtomicU8>,
     pub output_on: Arc<AtomicBool>,
-    pub triggered: Arc<AtomicBool>,
-    pub reset_detected: Arc<AtomicBool>,
-    min_val: i32,
-    max_val: i32,
     pub debug_mode: Arc<AtomicBool>,
     pub settings: Arc<Mutex<Settings>>,
-    pub manual_output_override: Arc<AtomicBool>,
+    // Bumped after settings are replaced, so the control task only re-reads them on change
+    settings_version: Arc<AtomicU32>,
     pub manual_output_state: Arc<AtomicBool>,
     pub current_run: Arc<AtomicI32>,
     pub total_runs: Arc<AtomicI32>,
-    pub safe_stop_active: Arc<AtomicBool>,
+    pub interlocks: Arc<Mutex<InterlockMonitor>>,
+    pub alarms: Arc<Mutex<AlarmManager>>,
+    pub events: Arc<Mutex<EventLog>>,
+    pub results: Arc<Mutex<JobResults>>,
+    pub feedback: Arc<Mutex<FeedbackLog>>,
+    pub stalled: Arc<AtomicBool>,
+    pub last_safe_stop: Arc<Mutex<Option<SafeStopReport>>>,
+    pub snapshot: SnapshotCell,
+    // Set for good once any of the mutexes above has been found poisoned
+    lock_poisoned: Arc<AtomicBool>,
+    boot_time: Instant,
+}
+
+impl Default for RotaryEncoderState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RotaryEncoderState {
-    pub fn new(min_val: i32, max_val: i32) -> Self {
+    /// Travel range and count limits come from `Settings::travel`.
+    pub fn new() -> Self {
         Self {
-            value: Arc::new(AtomicI32::new(min_val)),
+            value: Arc::new(AtomicI32::new(0)),
             target_angles: Arc::new(Mutex::new(Vec::new())),
-            current_target_index: Arc::new(Mutex::new(0)),
-            encoder_active: Arc::new(AtomicBool::new(false)),
+            current_target_index: Arc::new(AtomicUsize::new(0)),
+            program_version: Arc::new(AtomicU32::new(0)),
+            bend_options: Arc::new(Mutex::new(Vec::new())),
+            last_overtravel: Arc::new(Mutex::new(None)),
+            machine_state: Arc::new(AtomicU8::new(MachineState::Idle as u8)),
             output_on: Arc::new(AtomicBool::new(false)),
-            triggered: Arc::new(AtomicBool::new(false)),
-            reset_detected: Arc::new(AtomicBool::new(false)),
-            min_val,
-            max_val,
             debug_mode: Arc::new(AtomicBool::new(false)),
             settings: Arc::new(Mutex::new(Settings::default())),
-            manual_output_override: Arc::new(AtomicBool::new(false)),
+            settings_version: Arc::new(AtomicU32::new(0)),
             manual_output_state: Arc::new(AtomicBool::new(false)),
             current_run: Arc::new(AtomicI32::new(0)),
             total_runs: Arc::new(AtomicI32::new(1)),
-            safe_stop_active: Arc::new(AtomicBool::new(false)),
+            interlocks: Arc::new(Mutex::new(InterlockMonitor::new())),
+            alarms: Arc::new(Mutex::new(AlarmManager::new())),
+            events: Arc::new(Mutex::new(EventLog::new())),
+            results: Arc::new(Mutex::new(JobResults::new())),
+            feedback: Arc::new(Mutex::new(FeedbackLog::new())),
+            stalled: Arc::new(AtomicBool::new(false)),
+            last_safe_stop: Arc::new(Mutex::new(None)),
+            snapshot: SnapshotCell::new(),
+            lock_poisoned: Arc::new(AtomicBool::new(false)),
+            boot_time: Instant::now(),
         }
     }
 
+    /// Lock one of the shared mutexes. If a thread panicked while holding it
+    /// the data is recovered instead of panicking here too, and the state is
+    /// marked so the control task can fault the machine.
+    fn lock<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        mutex.lock().unwrap_or_else(|poisoned| {
+            if !self.lock_poisoned.swap(true, Ordering::SeqCst) {
+                log::error!("🛑 Shared state mutex poisoned - recovering data");
+            }
+            poisoned.into_inner()
+        })
+    }
+
+    /// A mutex was found poisoned. Stays set until restart, since the
+    /// recovered data may be inconsistent.
+    pub fn is_lock_poisoned(&self) -> bool {
+        self.lock_poisoned.load(Ordering::SeqCst)
+    }
+
+    /// Milliseconds since the state was created, used to timestamp events
+    pub fn uptime_ms(&self) -> u64 {
+        self.boot_time.elapsed().as_millis() as u64
+    }
+
     pub fn set_value(&self, val: i32) {
         self.value.store(val, Ordering::SeqCst);
     }
 
+    /// Set the count to the zero reference (`Settings::zero_offset`).
+    pub fn set_zero(&self) {
+        let settings = self.lock(&self.settings);
+        let steps = settings.step_range().bound(settings.degrees_to_steps(settings.zero_offset));
+        drop(settings);
+        self.set_value(steps);
+    }
+
     pub fn get_value(&self) -> i32 {
         self.value.load(Ordering::SeqCst)
     }
 
     pub fn get_angle(&self) -> f32 {
-        let divisor = {
-            let settings = self.settings.lock().expect("Settings mutex poisoned");
-            match settings.step_mode {
-                StepMode::Full => 1.0,
-                StepMode::Half => 2.0,
-            }
-        };
-        self.get_value() as f32 / divisor
+        self.lock(&self.settings).steps_to_degrees(self.get_value())
+    }
+
+    pub fn get_machine_state(&self) -> MachineState {
+        MachineState::from_u8(self.machine_state.load(Ordering::SeqCst))
+    }
+
+    /// Move to `to` if the transition is legal from the current state. Returns
+    /// the previous state.
+    pub fn transition_to(&self, to: MachineState) -> Result<MachineState, IllegalTransition> {
+        self.machine_state
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                MachineState::from_u8(current).can_transition_to(to).then_some(to as u8)
+            })
+            .map(MachineState::from_u8)
+            .map_err(|current| IllegalTransition { from: MachineState::from_u8(current), to })
+    }
+
+    /// Move from `from` to `to` only if the machine is currently in `from`.
+    pub fn transition_from(&self, from: MachineState, to: MachineState) -> bool {
+        debug_assert!(from.can_transition_to(to), "{:?} -> {:?}", from, to);
+        self.machine_state
+            .compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
     }
 
     pub fn is_active(&self) -> bool {
-        self.encoder_active.load(Ordering::SeqCst)
+        self.get_machine_state().is_running()
     }
 
     pub fn is_output_on(&self) -> bool {
         self.output_on.load(Ordering::SeqCst)
     }
 
-    pub fn set_target_angles(&self, angles: Vec<f32>) {
-        let settings = self.settings.lock().expect("Settings mutex poisoned");
-        let multiplier = match settings.step_mode {
-            StepMode::Full => 1.0,
-            StepMode::Half => 2.0,
-        };
+    /// Start a job with per-bend options. Bends without an entry in `options`
+    /// use the defaults from `Settings`.
+    pub fn set_program(&self, angles: Vec<f32>, mut options: Vec<BendOptions>) -> Result<(), StartError> {
+        if let Some(code) = self.blocking_alarm() {
+            return Err(StartError::AlarmActive(code));
+        }
+        let current = self.get_machine_state();
+        if !current.can_start() {
+            return Err(StartError::IllegalState(current));
+        }
+        if let Some(i) = options.iter().position(|o| o.validate().is_err()) {
+            return Err(StartError::InvalidOutputMode { bend: i + 1 });
+        }
+
+        let settings = self.lock(&self.settings);
+        let steps = angles.iter()
+            .enumerate()
+            .map(|(i, &angle)| {
+                let compensation = options.get(i).map_or(0.0, |o| o.compensation);
+                settings.target_steps(angle + compensation)
+                    .and_then(|_| settings.target_steps(angle))
+                    .map_err(|error| StartError::OutOfTravel { bend: i + 1, error })
+            })
+            .collect::<Result<Vec<i32>, _>>()?;
         let number_of_runs = settings.number_of_runs;
         drop(settings);
         
-        let mut targets = self.target_angles.lock()
-            .expect("Target angles mutex poisoned");
-        targets.clear();
-        // Convert degrees to steps, with validation
-        for angle in angles {
-            // Clamp angles to valid range [0, 360]
-            let clamped_angle = angle.max(0.0).min(360.0);
-            targets.push((clamped_angle * multiplier).round() as i32);
-        }
-        *self.current_target_index.lock()
-            .expect("Current target index mutex poisoned") = 0;
-        self.triggered.store(false, Ordering::SeqCst);
-        self.reset_detected.store(false, Ordering::SeqCst);
-        self.encoder_active.store(true, Ordering::SeqCst);
-        // Cancel any in-progress safe stop when Start is pressed
-        self.safe_stop_active.store(false, Ordering::SeqCst);
-        // Reset angle to 0 when Start button is pressed
-        self.set_value(0);
+        let mut targets = self.lock(&self.target_angles);
+        *targets = steps;
+        options.resize(targets.len(), BendOptions::default());
+        *self.lock(&self.bend_options) = options;
+        drop(targets);
+        self.program_version.fetch_add(1, Ordering::Release);
+        *self.lock(&self.last_overtravel) = None;
+        self.lock(&self.results).clear();
+        self.set_current_target_index(0);
+        // Re-zero when Start button is pressed
+        self.set_zero();
         // Initialize run counters
         self.reset_current_run();
         self.set_total_runs(number_of_runs as i32);
         self.increment_current_run(); // Start at run 1
+        // Arm last so the control loop only sees the new program. This also
+        // cancels any in-progress safe stop.
+        if !self.transition_from(current, MachineState::Armed) {
+            return Err(StartError::IllegalState(self.get_machine_state()));
+        }
+        Ok(())
     }
 
     pub fn stop(&self) {
-        self.encoder_active.store(false, Ordering::SeqCst);
-        // Reset triggered/reset state from any previous run
-        self.triggered.store(false, Ordering::SeqCst);
-        self.reset_detected.store(false, Ordering::SeqCst);
-        // Reset run counter
-        self.reset_current_run();
-        // Stop has highest priority - clear any manual output override
-        self.clear_manual_output();
         // Initiate safe stop: the main loop will turn the output pin ON to trigger
         // the machine to reverse direction.  When the angle drops below the
         // minimum_angle_threshold the main loop will turn the output pin OFF and
         // call complete_safe_stop() to finish the operation.
-        self.safe_stop_active.store(true, Ordering::SeqCst);
+        // Stop has highest priority: leaving Manual clears the manual override.
+        // Only a latched fault, which already holds the output off, refuses it.
+        if let Err(e) = self.transition_to(MachineState::SafeStop) {
+            log::warn!("Stop ignored: {}", e);
+            return;
+        }
+        // Reset run counter
+        self.reset_current_run();
         // Note: the angle value is intentionally NOT reset here so the main loop
         // can monitor it while safe stop is in progress.
     }
 
     pub fn is_safe_stop_active(&self) -> bool {
-        self.safe_stop_active.load(Ordering::SeqCst)
+        self.get_machine_state() == MachineState::SafeStop
     }
 
     pub fn complete_safe_stop(&self) {
-        self.safe_stop_active.store(false, Ordering::SeqCst);
-        // Now that the machine has returned to the safe position, reset the angle
-        self.set_value(0);
+        if self.transition_from(MachineState::SafeStop, MachineState::Idle) {
+            // Now that the machine has returned to the safe position, re-zero the angle
+            self.set_zero();
+        }
+    }
+
+    /// Abort an in-progress safe stop and let the output turn off. Returns
+    /// `false` if no safe stop was active.
+    pub fn abort_safe_stop(&self) -> bool {
+        if !self.transition_from(MachineState::SafeStop, MachineState::Idle) {
+            return false;
+        }
+        self.log_event(
+            EventKind::SafeStopAborted,
+            format!("Safe stop aborted at {:.1}°", self.get_angle()),
+            self.uptime_ms(),
+        );
+        true
+    }
+
+    pub fn get_last_safe_stop(&self) -> Option<SafeStopReport> {
+        *self.lock(&self.last_safe_stop)
+    }
+
+    pub fn set_last_safe_stop(&self, report: SafeStopReport) {
+        *self.lock(&self.last_safe_stop) = Some(report);
     }
 
     pub fn get_target_angles(&self) -> Vec<f32> {
-        let settings = self.settings.lock().expect("Settings mutex poisoned");
-        let divisor = match settings.step_mode {
-            StepMode::Full => 1.0,
-            StepMode::Half => 2.0,
-        };
-        drop(settings);
-        
-        self.target_angles
-            .lock()
-            .expect("Target angles mutex poisoned")
-            .iter()
-            .map(|&v| v as f32 / divisor)
+        let settings = self.get_settings();
+        self.lock(&self.target_angles).iter()
+            .map(|&v| settings.steps_to_degrees(v))
             .collect()
     }
 
+    pub fn get_last_overtravel(&self) -> Option<OvertravelRecord> {
+        *self.lock(&self.last_overtravel)
+    }
+
+    pub fn set_last_overtravel(&self, record: OvertravelRecord) {
+        *self.lock(&self.last_overtravel) = Some(record);
+    }
+
     pub fn get_current_target_index(&self) -> usize {
-        *self.current_target_index.lock()
-            .expect("Current target index mutex poisoned")
+        self.current_target_index.load(Ordering::SeqCst)
     }
 
-    pub fn set_debug_mode(&self, enabled: bool) {
-        self.debug_mode.store(enabled, Ordering::Release);
+    pub fn set_current_target_index(&self, index: usize) {
+        self.current_target_index.store(index, Ordering::SeqCst);
     }
 
-    pub fn is_debug_mode(&self) -> bool {
-        self.debug_mode.load(Ordering::Acquire)
+    /// Changes every time a new program is loaded or edited
+    pub fn program_version(&self) -> u32 {
+        self.program_version.load(Ordering::Acquire)
     }
 
-    pub fn is_target_reached(&self) -> bool {
-        self.triggered.load(Ordering::Acquire)
+    /// Change bends of the running program from `first_editable` on without
+    /// touching the position, run counter or encoder value.
+    pub fn edit_program(&self, edit: ProgramEdit, current: usize, first_editable: usize) -> Result<(), EditError> {
+        let settings = self.get_settings();
+        let mut targets = self.lock(&self.target_angles);
+        let mut options = self.lock(&self.bend_options);
+        program::apply_edit(&mut targets, &mut options, edit, current, first_editable, |angle| {
+            settings.target_steps(angle)
+        })?;
+        drop(options);
+        drop(targets);
+        self.program_version.fetch_add(1, Ordering::Release);
+        Ok(())
     }
 
-    fn bound(&self, value: i32) -> i32 {
-        if value < self.min_val {
-            self.min_val
-        } else if value > self.max_val {
-            self.max_val
-        } else {
-            value
-        }
+    /// Replace the springback compensation of a bend in the active program.
+    /// The compensated target must stay within the travel range. Returns the
+    /// new program version.
+    pub fn set_compensation(&self, bend_index: usize, compensation: f32) -> Result<u32, FeedbackError> {
+        let settings = self.get_settings();
+        let targets = self.lock(&self.target_angles);
+        let mut options = self.lock(&self.bend_options);
+        let (Some(&target), Some(bend_options)) = (targets.get(bend_index), options.get_mut(bend_index)) else {
+            return Err(FeedbackError::BendRemoved { bend: bend_index + 1 });
+        };
+        settings
+            .target_steps(settings.steps_to_degrees(target) + compensation)
+            .map_err(FeedbackError::OutOfTravel)?;
+        bend_options.compensation = compensation;
+        drop(options);
+        drop(targets);
+        Ok(self.program_version.fetch_add(1, Ordering::AcqRel).wrapping_add(1))
+    }
+
+    /// Append a taught angle to the target list without starting a job.
+    /// Returns the new number of targets.
+    pub fn append_target(&self, angle: f32) -> Result<usize, OutOfTravel> {
+        let steps = self.lock(&self.settings).target_steps(angle)?;
+        let mut targets = self.lock(&self.target_angles);
+        targets.push(steps);
+        self.lock(&self.bend_options).push(BendOptions::default());
+        let count = targets.len();
+        drop(targets);
+        self.program_version.fetch_add(1, Ordering::Release);
+        Ok(count)
+    }
+
+    /// Empty the target list, e.g. before teaching a new program
+    pub fn clear_targets(&self) {
+        let mut targets = self.lock(&self.target_angles);
+        targets.clear();
+        self.lock(&self.bend_options).clear();
+        drop(targets);
+        self.program_version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Copy the step targets and bend options into the given buffers
+    pub fn copy_program(&self, targets: &mut Vec<i32>, options: &mut Vec<BendOptions>) {
+        targets.clone_from(&self.lock(&self.target_angles));
+        options.clone_from(&self.lock(&self.bend_options));
+    }
+
+    pub fn set_debug_mode(&self, enabled: bool) {
+        self.debug_mode.store(enabled, Ordering::Release);
+    }
+
+    pub fn is_debug_mode(&self) -> bool {
+        self.debug_mode.load(Ordering::Acquire)
     }
 
-    // Update encoder value based on direction from rotary-encoder-embedded library
-    pub fn update_from_direction(&self, direction: i32) {
+    /// Update the count from a direction reported by the rotary-encoder-embedded
+    /// library. The forward direction and travel range are supplied by the
+    /// caller so the control loop does not have to lock the settings.
+    pub fn move_by(&self, direction: i32, forward_direction: ForwardDirection, range: StepRange) {
         if direction != 0 {
-            let settings = self.settings.lock().expect("Settings mutex poisoned");
-            let forward_direction = settings.forward_direction;
-            drop(settings);
-            
             let old_value = self.get_value();
             // Apply direction based on forward_direction setting
-            let adjusted_direction = match forward_direction {
-                ForwardDirection::Clockwise => direction,
-                ForwardDirection::CounterClockwise => -direction,
-            };
-            let new_value = self.bound(old_value + adjusted_direction);
+            let adjusted_direction = forward_direction.apply(direction);
+            let new_value = range.bound(old_value + adjusted_direction);
             self.value.store(new_value, Ordering::SeqCst);
             
             if self.is_debug_mode() {
@@ -250,7 +634,7 @@ impl RotaryEncoderState {
     }
 
     pub fn get_settings(&self) -> Settings {
-        let mut settings = self.settings.lock().expect("Settings mutex poisoned").clone();
+        let mut settings = self.lock(&self.settings).clone();
         // Sync debug_enabled with the atomic debug_mode
         settings.debug_enabled = self.is_debug_mode();
         settings
@@ -259,21 +643,35 @@ impl RotaryEncoderState {
     pub fn set_settings(&self, new_settings: Settings) {
         // Sync the atomic debug_mode with debug_enabled from settings
         self.set_debug_mode(new_settings.debug_enabled);
-        let mut settings = self.settings.lock().expect("Settings mutex poisoned");
+        let mut settings = self.lock(&self.settings);
         *settings = new_settings;
+        drop(settings);
+        self.settings_version.fetch_add(1, Ordering::Release);
     }
 
-    pub fn set_manual_output(&self, state: bool) {
-        self.manual_output_override.store(true, Ordering::SeqCst);
-        self.manual_output_state.store(state, Ordering::SeqCst);
+    /// Changes every time the settings are replaced
+    pub fn settings_version(&self) -> u32 {
+        self.settings_version.load(Ordering::Acquire)
     }
 
-    pub fn clear_manual_output(&self) {
-        self.manual_output_override.store(false, Ordering::SeqCst);
+    /// Latest snapshot published by the control loop
+    pub fn get_snapshot(&self) -> ControlSnapshot {
+        self.snapshot.read()
     }
 
-    pub fn is_manual_output_override(&self) -> bool {
-        self.manual_output_override.load(Ordering::SeqCst)
+    /// Drive the output manually. Only allowed while no job is running.
+    pub fn set_manual_output(&self, state: bool) -> Result<(), IllegalTransition> {
+        let current = self.get_machine_state();
+        if !current.can_transition_to(MachineState::Manual) {
+            return Err(IllegalTransition { from: current, to: MachineState::Manual });
+        }
+        self.manual_output_state.store(state, Ordering::SeqCst);
+        self.transition_to(MachineState::Manual)?;
+        Ok(())
+    }
+
+    pub fn clear_manual_output(&self) {
+        self.transition_from(MachineState::Manual, MachineState::Idle);
     }
 
     pub fn get_manual_output_state(&self) -> bool {
@@ -296,39 +694,204 @@ impl RotaryEncoderState {
         self.current_run.fetch_add(1, Ordering::SeqCst);
     }
 
+    pub fn set_current_run(&self, run: i32) {
+        self.current_run.store(run, Ordering::SeqCst);
+    }
+
     pub fn reset_current_run(&self) {
         self.current_run.store(0, Ordering::SeqCst);
     }
+
+    /// Record the sampled state of an interlock input. Returns `true` when the
+    /// input has just opened.
+    pub fn update_interlock(&self, input: InterlockInput, open: bool, timestamp_ms: u64) -> bool {
+        self.lock(&self.interlocks).update(input, open, timestamp_ms)
+    }
+
+    pub fn get_interlock_status(&self) -> InterlockStatus {
+        let fault_latched = self.lock(&self.alarms).is_latched(AlarmCode::Interlock);
+        self.lock(&self.interlocks).status(fault_latched)
+    }
+
+    /// Raise an alarm condition. A new `Fault` occurrence moves the machine to
+    /// `Fault`, so the control loop comes back up in `Idle` after acknowledge.
+    pub fn raise_alarm(&self, code: AlarmCode, message: impl Into<String>, timestamp_ms: u64) -> bool {
+        let message = message.into();
+        let raised = self.lock(&self.alarms).raise(code, message.clone(), timestamp_ms);
+        if raised {
+            match code.severity() {
+                Severity::Warning => log::warn!("⚠️ Alarm {:?}: {}", code, message),
+                Severity::Fault => {
+                    log::error!("🛑 Alarm {:?}: {}", code, message);
+                    self.enter_fault();
+                }
+            }
+        }
+        raised
+    }
+
+    pub fn clear_alarm_condition(&self, code: AlarmCode, timestamp_ms: u64) {
+        self.lock(&self.alarms).clear_condition(code, timestamp_ms);
+    }
+
+    pub fn acknowledge_alarm(&self, code: AlarmCode) -> Result<(), AlarmError> {
+        let timestamp_ms = self.uptime_ms();
+        self.lock(&self.alarms).acknowledge(code, timestamp_ms)?;
+        self.leave_fault_if_cleared();
+        Ok(())
+    }
+
+    /// Acknowledge every alarm whose condition has cleared. Returns the codes
+    /// that are still active.
+    pub fn acknowledge_all_alarms(&self) -> Vec<AlarmCode> {
+        let timestamp_ms = self.uptime_ms();
+        let still_active = self.lock(&self.alarms).acknowledge_all(timestamp_ms);
+        self.leave_fault_if_cleared();
+        still_active
+    }
+
+    fn leave_fault_if_cleared(&self) {
+        if !self.is_alarm_blocking() {
+            self.transition_from(MachineState::Fault, MachineState::Idle);
+        }
+    }
+
+    pub fn is_alarm_blocking(&self) -> bool {
+        self.lock(&self.alarms).is_blocking()
+    }
+
+    /// First latched alarm with `Fault` severity, if any
+    pub fn blocking_alarm(&self) -> Option<AlarmCode> {
+        self.lock(&self.alarms).latched_codes()
+            .into_iter()
+            .find(|code| code.severity() == Severity::Fault)
+    }
+
+    pub fn get_latched_alarms(&self) -> Vec<AlarmCode> {
+        self.lock(&self.alarms).latched_codes()
+    }
+
+    pub fn get_alarm_status(&self) -> AlarmStatus {
+        self.lock(&self.alarms).status()
+    }
+
+    pub fn log_event(&self, kind: EventKind, message: impl Into<String>, timestamp_ms: u64) {
+        let message = message.into();
+        log::info!("📝 {:?}: {}", kind, message);
+        self.lock(&self.events).push(kind, message, timestamp_ms);
+    }
+
+    pub fn get_events(&self) -> Vec<Event> {
+        self.lock(&self.events).events()
+    }
+
+    pub fn push_result(&self, result: BendResult) {
+        self.lock(&self.results).push(result);
+    }
+
+    pub fn get_results(&self) -> ResultsReport {
+        self.lock(&self.results).report()
+    }
+
+    pub fn get_last_result(&self) -> Option<BendResult> {
+        self.lock(&self.results).last()
+    }
+
+    pub fn set_last_result_program_version(&self, program_version: u32) {
+        self.lock(&self.results).set_last_program_version(program_version);
+    }
+
+    pub fn push_feedback(&self, record: FeedbackRecord) {
+        self.lock(&self.feedback).push(record);
+    }
+
+    pub fn get_feedback_history(&self) -> Vec<FeedbackRecord> {
+        self.lock(&self.feedback).records()
+    }
+
+    /// Rejected bends in the current job and the last of them
+    pub fn get_rejects(&self) -> (u32, Option<BendResult>) {
+        let results = self.lock(&self.results);
+        (results.rejects(), results.last_reject())
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.stalled.load(Ordering::SeqCst)
+    }
+
+    /// Abort the running job without a safe stop: used when a blocking alarm
+    /// is raised and the output must stay off rather than reverse the machine.
+    fn enter_fault(&self) {
+        if let Ok(previous) = self.transition_to(MachineState::Fault) {
+            if previous != MachineState::Fault {
+                self.reset_current_run();
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Feed one encoder direction with the state's own settings, as the control loop does
+    fn move_by(state: &RotaryEncoderState, direction: i32) {
+        let settings = state.get_settings();
+        state.move_by(direction, settings.forward_direction, settings.step_range());
+    }
+
     fn make_state_with_step_mode(mode: StepMode) -> RotaryEncoderState {
-        let state = RotaryEncoderState::new(0, 720);
-        let mut settings = Settings::default();
-        settings.step_mode = mode;
-        state.set_settings(settings);
+        let state = RotaryEncoderState::new();
+        state.set_settings(Settings { resolution: mode.into(), ..Default::default() });
         state
     }
 
-    // --- StepMode default ---
+    // --- Resolution default and step mode migration ---
 
     #[test]
-    fn default_step_mode_is_full() {
+    fn default_resolution_is_full_step() {
         let settings = Settings::default();
-        assert_eq!(settings.step_mode, StepMode::Full);
+        assert_eq!(settings.resolution, EncoderResolution::from(StepMode::Full));
     }
 
-    // --- set_target_angles: rounding instead of truncation ---
+    #[test]
+    fn stored_step_mode_is_migrated_to_resolution() {
+        let settings = Settings::from_json(br#"{"step_mode":"Half","number_of_runs":3}"#).unwrap();
+        assert_eq!(settings.resolution.counts_per_degree(), 2.0);
+        assert_eq!(settings.step_mode, None);
+        assert_eq!(settings.number_of_runs, 3);
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(!json.contains("step_mode"), "Only the new field is written back");
+        assert_eq!(Settings::from_json(json.as_bytes()).unwrap().resolution, settings.resolution);
+    }
+
+    #[test]
+    fn calibration_replaces_step_mode_conversion() {
+        let state = make_state_with_step_mode(StepMode::Half);
+        let mut settings = state.get_settings();
+        settings.calibration = Some(Calibration { degrees_per_step: 0.25, offset: 1.0 });
+        state.set_settings(settings);
+        state.set_program(vec![46.0], Vec::new()).unwrap();
+        assert_eq!(*state.target_angles.lock().unwrap(), vec![180]);
+        state.set_value(40);
+        assert_eq!(state.get_angle(), 11.0);
+    }
+
+    #[test]
+    fn settings_with_zero_calibration_scale_are_invalid() {
+        let calibration = Some(Calibration { degrees_per_step: 0.0, offset: 0.0 });
+        assert!(Settings::default().validate().is_ok());
+        assert!(Settings { calibration, ..Settings::default() }.validate().is_err());
+    }
+
+    // --- set_program: rounding instead of truncation ---
 
     #[test]
     fn target_angle_half_degree_full_mode_rounds_to_one_step() {
         // With Full mode (multiplier=1.0), 0.5° should round to 1 step, not truncate to 0.
         // Previously `(0.5 * 1.0) as i32 = 0` caused immediate trigger (critical bug).
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![0.5]);
+        state.set_program(vec![0.5], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 1, "0.5° in Full mode must round to 1 step, not truncate to 0");
     }
@@ -337,7 +900,7 @@ mod tests {
     fn target_angle_zero_not_set_for_half_degree_full_mode() {
         // Ensure the target is never 0 for a 0.5° input in Full mode (prevents immediate trigger).
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![0.5]);
+        state.set_program(vec![0.5], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_ne!(targets[0], 0, "Target of 0 steps would trigger immediately at start");
     }
@@ -345,7 +908,7 @@ mod tests {
     #[test]
     fn target_angle_one_degree_full_mode_is_one_step() {
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![1.0]);
+        state.set_program(vec![1.0], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 1);
     }
@@ -354,7 +917,7 @@ mod tests {
     fn target_angle_half_degree_half_mode_is_one_step() {
         // With Half mode (multiplier=2.0), 0.5° = (0.5 * 2.0).round() = 1 step.
         let state = make_state_with_step_mode(StepMode::Half);
-        state.set_target_angles(vec![0.5]);
+        state.set_program(vec![0.5], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 1);
     }
@@ -362,7 +925,7 @@ mod tests {
     #[test]
     fn target_angle_one_degree_half_mode_is_two_steps() {
         let state = make_state_with_step_mode(StepMode::Half);
-        state.set_target_angles(vec![1.0]);
+        state.set_program(vec![1.0], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 2);
     }
@@ -370,7 +933,7 @@ mod tests {
     #[test]
     fn target_angle_45_degrees_full_mode() {
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![45.0]);
+        state.set_program(vec![45.0], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 45);
     }
@@ -378,7 +941,7 @@ mod tests {
     #[test]
     fn target_angle_45_degrees_half_mode() {
         let state = make_state_with_step_mode(StepMode::Half);
-        state.set_target_angles(vec![45.0]);
+        state.set_program(vec![45.0], Vec::new()).unwrap();
         let targets = state.target_angles.lock().unwrap();
         assert_eq!(targets[0], 90);
     }
@@ -404,7 +967,7 @@ mod tests {
     #[test]
     fn get_target_angles_round_trip_full_mode() {
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![45.0, 90.0, 180.0]);
+        state.set_program(vec![45.0, 90.0, 180.0], Vec::new()).unwrap();
         let retrieved = state.get_target_angles();
         assert_eq!(retrieved, vec![45.0, 90.0, 180.0]);
     }
@@ -412,42 +975,72 @@ mod tests {
     #[test]
     fn get_target_angles_round_trip_half_mode() {
         let state = make_state_with_step_mode(StepMode::Half);
-        state.set_target_angles(vec![45.0, 90.0]);
+        state.set_program(vec![45.0, 90.0], Vec::new()).unwrap();
         let retrieved = state.get_target_angles();
         assert_eq!(retrieved, vec![45.0, 90.0]);
     }
 
-    // --- angle clamping ---
+    // --- travel range ---
 
     #[test]
-    fn target_angle_negative_clamped_to_zero() {
+    fn target_angle_negative_is_rejected() {
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![-10.0]);
-        let targets = state.target_angles.lock().unwrap();
-        assert_eq!(targets[0], 0);
+        let error = OutOfTravel { angle: -10.0, min_angle: 0.0, max_angle: 360.0 };
+        assert_eq!(
+            state.set_program(vec![45.0, -10.0], Vec::new()),
+            Err(StartError::OutOfTravel { bend: 2, error })
+        );
+        assert_eq!(state.get_machine_state(), MachineState::Idle, "Job must not start");
     }
 
     #[test]
-    fn target_angle_above_360_clamped_to_360() {
+    fn target_angle_above_travel_is_rejected() {
         let state = make_state_with_step_mode(StepMode::Full);
-        state.set_target_angles(vec![400.0]);
-        let targets = state.target_angles.lock().unwrap();
-        assert_eq!(targets[0], 360);
+        assert!(matches!(state.set_program(vec![400.0], Vec::new()), Err(StartError::OutOfTravel { bend: 1, .. })));
+        assert!(state.target_angles.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn signed_travel_accepts_negative_targets() {
+        let state = make_state_with_step_mode(StepMode::Half);
+        let mut settings = state.get_settings();
+        settings.travel = Travel { min_angle: -90.0, max_angle: 90.0, signed: true, wrap_around: false };
+        state.set_settings(settings);
+        state.set_program(vec![-45.0], Vec::new()).unwrap();
+        assert_eq!(*state.target_angles.lock().unwrap(), vec![-90]);
+        for _ in 0..200 {
+            move_by(&state, -1);
+        }
+        assert_eq!(state.get_value(), -180, "Count stops at the minimum travel");
+        assert_eq!(state.get_angle(), -90.0);
+    }
+
+    #[test]
+    fn wrap_around_counts_continuously() {
+        let state = make_state_with_step_mode(StepMode::Full);
+        let mut settings = state.get_settings();
+        settings.travel.wrap_around = true;
+        state.set_settings(settings);
+        state.set_value(359);
+        move_by(&state, 1);
+        assert_eq!(state.get_value(), 0);
+        move_by(&state, -1);
+        assert_eq!(state.get_value(), 359);
     }
 
-    // --- update_from_direction ---
+    // --- move_by ---
 
     #[test]
-    fn update_from_direction_clockwise_increments() {
-        let state = RotaryEncoderState::new(0, 720);
-        state.update_from_direction(1);
+    fn move_by_clockwise_increments() {
+        let state = RotaryEncoderState::new();
+        move_by(&state, 1);
         assert_eq!(state.get_value(), 1);
     }
 
     #[test]
-    fn update_from_direction_anticlockwise_decrements_clamped_at_min() {
-        let state = RotaryEncoderState::new(0, 720);
-        state.update_from_direction(-1);
+    fn move_by_anticlockwise_decrements_clamped_at_min() {
+        let state = RotaryEncoderState::new();
+        move_by(&state, -1);
         // Clamped at min_val=0
         assert_eq!(state.get_value(), 0);
     }
@@ -456,7 +1049,7 @@ mod tests {
 
     #[test]
     fn stop_initiates_safe_stop() {
-        let state = RotaryEncoderState::new(0, 720);
+        let state = RotaryEncoderState::new();
         state.set_value(100); // non-zero angle
         state.stop();
         assert!(
@@ -464,13 +1057,13 @@ mod tests {
             "stop() must activate safe stop mode so the output pin turns ON to trigger machine reversal"
         );
         assert!(!state.is_active(), "stop() must deactivate the encoder");
-        assert!(!state.is_manual_output_override(), "stop() must clear manual output override");
+        assert_ne!(state.get_machine_state(), MachineState::Manual, "stop() must clear manual output override");
         assert_eq!(state.get_current_run(), 0, "stop() must reset the run counter");
     }
 
     #[test]
     fn stop_preserves_angle_for_safe_stop_monitoring() {
-        let state = RotaryEncoderState::new(0, 720);
+        let state = RotaryEncoderState::new();
         state.set_value(200);
         state.stop();
         assert_eq!(
@@ -482,8 +1075,8 @@ mod tests {
 
     #[test]
     fn stop_deactivates_encoder() {
-        let state = RotaryEncoderState::new(0, 720);
-        state.set_target_angles(vec![45.0]);
+        let state = RotaryEncoderState::new();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
         assert!(state.is_active());
         state.stop();
         assert!(!state.is_active(), "stop() must deactivate the encoder");
@@ -491,31 +1084,31 @@ mod tests {
 
     #[test]
     fn stop_clears_manual_output_override() {
-        let state = RotaryEncoderState::new(0, 720);
-        state.set_manual_output(true);
-        assert!(state.is_manual_output_override());
+        let state = RotaryEncoderState::new();
+        state.set_manual_output(true).unwrap();
+        assert_eq!(state.get_machine_state(), MachineState::Manual);
         state.stop();
         assert!(
-            !state.is_manual_output_override(),
+            state.get_machine_state() != MachineState::Manual,
             "stop() must clear manual output override (Stop has highest priority)"
         );
     }
 
     #[test]
     fn stop_clears_manual_output_override_when_output_off() {
-        let state = RotaryEncoderState::new(0, 720);
-        state.set_manual_output(false);
-        assert!(state.is_manual_output_override());
+        let state = RotaryEncoderState::new();
+        state.set_manual_output(false).unwrap();
+        assert_eq!(state.get_machine_state(), MachineState::Manual);
         state.stop();
         assert!(
-            !state.is_manual_output_override(),
+            state.get_machine_state() != MachineState::Manual,
             "stop() must clear manual output override regardless of manual state"
         );
     }
 
     #[test]
     fn complete_safe_stop_clears_safe_stop_flag() {
-        let state = RotaryEncoderState::new(0, 720);
+        let state = RotaryEncoderState::new();
         state.stop(); // activates safe stop
         assert!(state.is_safe_stop_active());
         state.complete_safe_stop();
@@ -525,9 +1118,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn abort_safe_stop_clears_flag_and_keeps_angle() {
+        let state = RotaryEncoderState::new();
+        state.set_value(200);
+        state.stop();
+        assert!(state.abort_safe_stop());
+        assert!(!state.is_safe_stop_active());
+        assert_eq!(state.get_value(), 200, "Position is unknown after an abort and must not be zeroed");
+    }
+
+    #[test]
+    fn abort_safe_stop_without_safe_stop_is_rejected() {
+        let state = RotaryEncoderState::new();
+        assert!(!state.abort_safe_stop());
+    }
+
     #[test]
     fn complete_safe_stop_resets_angle_to_zero() {
-        let state = RotaryEncoderState::new(0, 720);
+        let state = RotaryEncoderState::new();
         state.set_value(200);
         state.stop();
         state.complete_safe_stop();
@@ -537,4 +1146,48 @@ mod tests {
             "complete_safe_stop() must reset the angle to 0"
         );
     }
+
+    // --- machine state ---
+
+    #[test]
+    fn new_state_is_idle() {
+        let state = RotaryEncoderState::new();
+        assert_eq!(state.get_machine_state(), MachineState::Idle);
+    }
+
+    #[test]
+    fn illegal_transition_is_rejected_and_state_kept() {
+        let state = RotaryEncoderState::new();
+        assert_eq!(
+            state.transition_to(MachineState::Returning),
+            Err(IllegalTransition { from: MachineState::Idle, to: MachineState::Returning })
+        );
+        assert_eq!(state.get_machine_state(), MachineState::Idle);
+    }
+
+    #[test]
+    fn start_rejected_mid_stroke() {
+        let state = RotaryEncoderState::new();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        state.transition_to(MachineState::Bending).unwrap();
+        state.set_value(30);
+        assert_eq!(
+            state.set_program(vec![90.0], Vec::new()),
+            Err(StartError::IllegalState(MachineState::Bending))
+        );
+        assert_eq!(state.get_value(), 30, "A rejected Start must not touch the running job");
+    }
+
+    #[test]
+    fn fault_returns_to_idle_after_acknowledge() {
+        let state = RotaryEncoderState::new();
+        state.set_program(vec![45.0], Vec::new()).unwrap();
+        state.raise_alarm(AlarmCode::EncoderError, "test", 0);
+        assert_eq!(state.get_machine_state(), MachineState::Fault);
+        state.stop();
+        assert_eq!(state.get_machine_state(), MachineState::Fault, "Stop must not leave Fault");
+        state.clear_alarm_condition(AlarmCode::EncoderError, 10);
+        state.acknowledge_alarm(AlarmCode::EncoderError).unwrap();
+        assert_eq!(state.get_machine_state(), MachineState::Idle);
+    }
 }
@@ -0,0 +1,153 @@
+use crate::calibration::CalibrationPoint;
+use crate::latency::LatencyStats;
+use crate::machine::MachineState;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
+
+/// Control loop status as of one iteration. The web server copies it out
+/// instead of reading the live control state field by field.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub struct ControlSnapshot {
+    /// Incremented on every iteration of the control loop
+    pub sequence: u32,
+    pub timestamp_ms: u64,
+    pub machine_state: MachineState,
+    /// Pause requested, waiting for the current stroke to finish
+    pub pause_pending: bool,
+    pub steps: i32,
+    pub angle: f32,
+    /// Angular velocity from the encoder timestamps, positive while the angle increases
+    pub degrees_per_second: f32,
+    /// How far ahead of the target the output fires at this speed
+    pub lead_angle: f32,
+    pub current_target_index: usize,
+    pub output_on: bool,
+    pub current_run: i32,
+    pub total_runs: i32,
+    pub interlock_open: bool,
+    pub stalled: bool,
+    pub idle_ms: u32,
+    pub teach_mode: bool,
+    /// Peak of the last manual stroke seen in teach mode
+    pub teach_peak: Option<f32>,
+    /// Points captured so far by the calibration routine
+    pub calibration_points: [Option<CalibrationPoint>; 2],
+    /// Measured time from the output firing to the ram reversing
+    pub latency: LatencyStats,
+    /// Version of the settings the control loop was running with
+    pub settings_version: u32,
+}
+
+impl Default for ControlSnapshot {
+    fn default() -> Self {
+        Self {
+            sequence: 0,
+            timestamp_ms: 0,
+            machine_state: MachineState::Idle,
+            pause_pending: false,
+            steps: 0,
+            angle: 0.0,
+            degrees_per_second: 0.0,
+            lead_angle: 0.0,
+            current_target_index: 0,
+            output_on: false,
+            current_run: 0,
+            total_runs: 1,
+            interlock_open: false,
+            stalled: false,
+            idle_ms: 0,
+            teach_mode: false,
+            teach_peak: None,
+            calibration_points: [None; 2],
+            latency: LatencyStats::default(),
+            settings_version: 0,
+        }
+    }
+}
+
+/// Double buffer the control task publishes its snapshot into. Each publish
+/// goes into the slot readers were not pointed at, then the index flips to it.
+/// The control task never waits: a reader only holds one slot while copying it
+/// out, so with the single web server task a publish always finds a free slot
+/// and a read is at most one control iteration old. A publish is only dropped
+/// if concurrent readers hold both slots.
+#[derive(Clone, Default)]
+pub struct SnapshotCell {
+    slots: Arc<[Mutex<ControlSnapshot>; 2]>,
+    latest: Arc<AtomicUsize>,
+}
+
+impl SnapshotCell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `false` if both slots were busy and the snapshot was dropped.
+    pub fn publish(&self, snapshot: &ControlSnapshot) -> bool {
+        let back = 1 - self.latest.load(Ordering::Acquire);
+        // Fall back to the front slot if a slow reader is still copying the back one
+        for index in [back, 1 - back] {
+            let written = match self.slots[index].try_lock() {
+                Ok(mut slot) => {
+                    *slot = *snapshot;
+                    true
+                }
+                // A plain `Copy` value cannot be left half-written, so poisoning is harmless
+                Err(TryLockError::Poisoned(e)) => {
+                    *e.into_inner() = *snapshot;
+                    true
+                }
+                Err(TryLockError::WouldBlock) => false,
+            };
+            if written {
+                self.latest.store(index, Ordering::Release);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn read(&self) -> ControlSnapshot {
+        let index = self.latest.load(Ordering::Acquire);
+        *self.slots[index].lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn published_snapshot_is_read_back() {
+        let cell = SnapshotCell::new();
+        let snapshot = ControlSnapshot { sequence: 7, steps: 90, ..Default::default() };
+        assert!(cell.publish(&snapshot));
+        assert_eq!(cell.read(), snapshot);
+    }
+
+    #[test]
+    fn publish_goes_to_other_slot_while_reader_copies() {
+        let cell = SnapshotCell::new();
+        cell.publish(&ControlSnapshot { sequence: 1, ..Default::default() });
+        let front = cell.latest.load(Ordering::Acquire);
+        let guard = cell.slots[front].lock().unwrap();
+        // Every iteration still gets out while the reader holds the front slot
+        for sequence in 2..5 {
+            assert!(cell.publish(&ControlSnapshot { sequence, ..Default::default() }));
+        }
+        assert_eq!(*guard, ControlSnapshot { sequence: 1, ..Default::default() });
+        drop(guard);
+        assert_eq!(cell.read().sequence, 4);
+    }
+
+    #[test]
+    fn publish_is_skipped_while_both_slots_are_held() {
+        let cell = SnapshotCell::new();
+        let first = cell.slots[0].lock().unwrap();
+        let second = cell.slots[1].lock().unwrap();
+        assert!(!cell.publish(&ControlSnapshot { sequence: 1, ..Default::default() }));
+        drop((first, second));
+        assert_eq!(cell.read().sequence, 0);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::machine::MachineState;
+use crate::travel::OutOfTravel;
+use serde::Deserialize;
+use std::fmt;
+
+/// Which angle a teach action stores.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TeachSource {
+    /// Peak of the last completed manual stroke
+    #[default]
+    Peak,
+    /// Angle the ram is at right now
+    Current,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TeachError {
+    NotInTeachMode,
+    /// Teach mode is only available with no job running (Idle or Manual)
+    Busy(MachineState),
+    /// No manual stroke has been completed since teach mode was enabled
+    NoPeak,
+    OutOfTravel(OutOfTravel),
+}
+
+impl fmt::Display for TeachError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TeachError::NotInTeachMode => write!(f, "Teach mode is not enabled"),
+            TeachError::Busy(state) => write!(f, "Teach mode is not available while {:?}", state),
+            TeachError::NoPeak => write!(f, "No stroke completed yet"),
+            TeachError::OutOfTravel(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Follows manual strokes in teach mode. A stroke starts once the ram goes
+/// above `start_angle` and ends when it comes back below `end_angle`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeakTracker {
+    stroke_peak: Option<f32>,
+    last_peak: Option<f32>,
+}
+
+impl PeakTracker {
+    /// Returns the peak when a stroke has just ended.
+    pub fn update(&mut self, angle: f32, start_angle: f32, end_angle: f32) -> Option<f32> {
+        match self.stroke_peak {
+            Some(peak) if angle < end_angle => {
+                self.stroke_peak = None;
+                self.last_peak = Some(peak);
+                Some(peak)
+            }
+            Some(peak) => {
+                self.stroke_peak = Some(peak.max(angle));
+                None
+            }
+            None if angle > start_angle => {
+                self.stroke_peak = Some(angle);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Peak of the last completed stroke
+    pub fn last_peak(&self) -> Option<f32> {
+        self.last_peak
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(tracker: &mut PeakTracker, angles: &[f32]) -> Option<f32> {
+        angles.iter().filter_map(|&angle| tracker.update(angle, 5.0, 2.5)).last()
+    }
+
+    #[test]
+    fn peak_is_recorded_when_stroke_ends() {
+        let mut tracker = PeakTracker::default();
+        assert_eq!(stroke(&mut tracker, &[0.0, 10.0, 45.5, 44.0, 20.0]), None);
+        assert_eq!(tracker.last_peak(), None, "Stroke still in progress");
+        assert_eq!(stroke(&mut tracker, &[2.0]), Some(45.5));
+        assert_eq!(tracker.last_peak(), Some(45.5));
+    }
+
+    #[test]
+    fn small_movements_at_top_are_not_strokes() {
+        let mut tracker = PeakTracker::default();
+        assert_eq!(stroke(&mut tracker, &[0.0, 4.0, 1.0, 4.5, 0.0]), None);
+        assert_eq!(tracker.last_peak(), None);
+    }
+
+    #[test]
+    fn next_stroke_replaces_last_peak() {
+        let mut tracker = PeakTracker::default();
+        stroke(&mut tracker, &[30.0, 0.0]);
+        stroke(&mut tracker, &[60.0, 58.0, 0.0]);
+        assert_eq!(tracker.last_peak(), Some(60.0));
+        tracker.reset();
+        assert_eq!(tracker.last_peak(), None);
+    }
+}
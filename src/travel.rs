@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Allowed travel of the beam, in degrees, and what the count does at its ends.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Travel {
+    pub min_angle: f32,
+    pub max_angle: f32,
+    /// Allow angles below zero, e.g. with the zero reference part way down the stroke
+    pub signed: bool,
+    /// Continuous rotation: the count wraps from the maximum back to the
+    /// minimum instead of stopping at the ends
+    pub wrap_around: bool,
+}
+
+impl Default for Travel {
+    fn default() -> Self {
+        Self {
+            min_angle: 0.0,
+            max_angle: 360.0,
+            signed: false,
+            wrap_around: false,
+        }
+    }
+}
+
+/// An angle outside the configured travel range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutOfTravel {
+    pub angle: f32,
+    pub min_angle: f32,
+    pub max_angle: f32,
+}
+
+impl fmt::Display for OutOfTravel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Angle {:.1}° is outside the travel range {:.1}° to {:.1}°",
+            self.angle, self.min_angle, self.max_angle
+        )
+    }
+}
+
+impl Travel {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.min_angle.is_finite() && self.max_angle.is_finite()) {
+            return Err("Travel range must be a number of degrees".to_string());
+        }
+        if self.max_angle <= self.min_angle {
+            return Err(format!(
+                "Maximum travel {}° must be above the minimum {}°",
+                self.max_angle, self.min_angle
+            ));
+        }
+        if self.min_angle < 0.0 && !self.signed {
+            return Err(format!("Minimum travel {}° is negative; enable signed travel", self.min_angle));
+        }
+        Ok(())
+    }
+
+    /// Check a target angle. With wrap-around the maximum is the same position
+    /// as the minimum, so only the minimum is accepted.
+    pub fn check(&self, angle: f32) -> Result<(), OutOfTravel> {
+        let above_max = if self.wrap_around { angle >= self.max_angle } else { angle > self.max_angle };
+        if angle < self.min_angle || above_max || angle.is_nan() {
+            return Err(OutOfTravel { angle, min_angle: self.min_angle, max_angle: self.max_angle });
+        }
+        Ok(())
+    }
+}
+
+/// Travel range converted to encoder counts, used by the control loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepRange {
+    pub min: i32,
+    pub max: i32,
+    pub wrap_around: bool,
+}
+
+impl StepRange {
+    /// Keep a count within the range, clamping at the ends or wrapping around
+    pub fn bound(&self, value: i32) -> i32 {
+        if self.wrap_around && self.max > self.min {
+            self.min + (value - self.min).rem_euclid(self.max - self.min)
+        } else {
+            value.clamp(self.min, self.max.max(self.min))
+        }
+    }
+
+    /// The count is pinned at an end of the range while `motion` (+1 up, -1
+    /// down) pushes against it. The lower end only counts below zero: zero is
+    /// the top of the stroke, where the count is clamped on purpose while the
+    /// ram settles. Never true with wrap-around.
+    pub fn is_saturated(&self, value: i32, motion: i32) -> bool {
+        (motion > 0 && value >= self.max || motion < 0 && value <= self.min) && self.is_at_limit(value)
+    }
+
+    /// The count is at an end `is_saturated` reports
+    pub fn is_at_limit(&self, value: i32) -> bool {
+        !self.wrap_around && (value >= self.max || self.min < 0 && value <= self.min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_is_clamped_at_the_ends() {
+        let range = StepRange { min: -20, max: 720, wrap_around: false };
+        assert_eq!(range.bound(-21), -20);
+        assert_eq!(range.bound(721), 720);
+        assert_eq!(range.bound(100), 100);
+        assert!(range.is_at_limit(720));
+        assert!(!range.is_at_limit(719));
+        assert!(range.is_saturated(720, 1));
+        assert!(!range.is_saturated(720, -1), "Moving away from the limit");
+        assert!(range.is_saturated(-20, -1));
+        assert!(!range.is_saturated(-20, 1));
+    }
+
+    #[test]
+    fn zero_is_not_a_limit_at_the_top_of_the_stroke() {
+        let range = StepRange { min: 0, max: 720, wrap_around: false };
+        assert!(!range.is_at_limit(0));
+        assert!(!range.is_saturated(0, -1));
+    }
+
+    #[test]
+    fn count_wraps_with_continuous_rotation() {
+        let range = StepRange { min: 0, max: 360, wrap_around: true };
+        assert_eq!(range.bound(360), 0);
+        assert_eq!(range.bound(361), 1);
+        assert_eq!(range.bound(-1), 359);
+        assert!(!range.is_at_limit(359));
+        assert!(!range.is_saturated(0, -1));
+        let signed = StepRange { min: -180, max: 180, wrap_around: true };
+        assert_eq!(signed.bound(180), -180);
+        assert_eq!(signed.bound(-181), 179);
+    }
+
+    #[test]
+    fn targets_are_checked_against_the_range() {
+        let travel = Travel::default();
+        assert!(travel.check(0.0).is_ok());
+        assert!(travel.check(360.0).is_ok());
+        assert_eq!(
+            travel.check(-10.0),
+            Err(OutOfTravel { angle: -10.0, min_angle: 0.0, max_angle: 360.0 })
+        );
+        assert!(travel.check(400.0).is_err());
+        assert!(travel.check(f32::NAN).is_err());
+        let wrapping = Travel { wrap_around: true, ..travel };
+        assert!(wrapping.check(360.0).is_err(), "360° is the same position as 0°");
+    }
+
+    #[test]
+    fn invalid_travel_is_rejected() {
+        let valid = Travel::default();
+        assert!(valid.validate().is_ok());
+        assert!(Travel { max_angle: 0.0, ..valid }.validate().is_err());
+        assert!(Travel { min_angle: -90.0, ..valid }.validate().is_err());
+        assert!(Travel { min_angle: -90.0, signed: true, ..valid }.validate().is_ok());
+        assert!(Travel { max_angle: f32::INFINITY, ..valid }.validate().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+// Count changes kept for the estimate; more smooths out the 1 ms timestamp resolution
+const SAMPLES: usize = 8;
+// No count change for this long means the ram has stopped
+const STOPPED_MS: u64 = 250;
+
+/// Angular velocity of the ram from the timestamps of encoder count changes.
+///
+/// The estimate spans the last few count changes rather than the last loop
+/// iteration, since at 1 ms polling a slow ram moves one count every few
+/// iterations. It falls off while no count comes in and is reset when the
+/// ram changes direction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VelocityEstimator {
+    // (angle, timestamp) of recent count changes, oldest first
+    samples: [(f32, u64); SAMPLES],
+    len: usize,
+}
+
+impl VelocityEstimator {
+    /// Feed the angle once per iteration.
+    pub fn update(&mut self, angle: f32, now_ms: u64) {
+        let Some(&(last_angle, last_ms)) = self.newest() else {
+            self.push(angle, now_ms);
+            return;
+        };
+        if now_ms.saturating_sub(last_ms) >= STOPPED_MS {
+            self.len = 0;
+        }
+        if angle == last_angle {
+            if self.len == 0 {
+                self.push(angle, now_ms);
+            }
+            return;
+        }
+        let reversed = self.len >= 2 && {
+            let previous = self.samples[self.len - 2].0;
+            (angle - last_angle).signum() != (last_angle - previous).signum()
+        };
+        if reversed {
+            self.samples[0] = (last_angle, last_ms);
+            self.len = 1;
+        }
+        self.push(angle, now_ms);
+    }
+
+    /// Degrees per second, positive while the angle is increasing
+    pub fn degrees_per_second(&self, now_ms: u64) -> f32 {
+        if self.len < 2 {
+            return 0.0;
+        }
+        let (first_angle, first_ms) = self.samples[0];
+        let (last_angle, last_ms) = self.samples[self.len - 1];
+        let idle_ms = now_ms.saturating_sub(last_ms);
+        if idle_ms >= STOPPED_MS {
+            return 0.0;
+        }
+        // Waiting longer than the average gap for the next count: the ram is slowing down
+        let average_gap_ms = (last_ms - first_ms) / (self.len as u64 - 1);
+        let end_ms = if idle_ms > average_gap_ms { now_ms } else { last_ms };
+        let span_ms = end_ms.saturating_sub(first_ms).max(1);
+        (last_angle - first_angle) * 1000.0 / span_ms as f32
+    }
+
+    fn newest(&self) -> Option<&(f32, u64)> {
+        self.len.checked_sub(1).map(|i| &self.samples[i])
+    }
+
+    fn push(&mut self, angle: f32, now_ms: u64) {
+        if self.len == SAMPLES {
+            self.samples.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.samples[self.len] = (angle, now_ms);
+        self.len += 1;
+    }
+}
+
+/// Degrees the ram travels during `latency_ms` at `degrees_per_second`.
+/// Only forward motion leads the trigger.
+pub fn lead_angle(degrees_per_second: f32, latency_ms: u32) -> f32 {
+    degrees_per_second.max(0.0) * latency_ms as f32 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One degree every `gap_ms`, starting at 0° and 0 ms
+    fn ramp(estimator: &mut VelocityEstimator, degrees: u32, gap_ms: u64) -> u64 {
+        let mut now_ms = 0;
+        for degree in 0..=degrees {
+            for _ in 0..gap_ms {
+                estimator.update(degree as f32, now_ms);
+                now_ms += 1;
+            }
+        }
+        now_ms - 1
+    }
+
+    #[test]
+    fn steady_motion_gives_its_speed() {
+        let mut estimator = VelocityEstimator::default();
+        let now_ms = ramp(&mut estimator, 20, 4);
+        assert_eq!(estimator.degrees_per_second(now_ms), 250.0);
+    }
+
+    #[test]
+    fn velocity_falls_off_and_stops_without_counts() {
+        let mut estimator = VelocityEstimator::default();
+        let now_ms = ramp(&mut estimator, 20, 4);
+        let slowing = estimator.degrees_per_second(now_ms + 20);
+        assert!(slowing > 0.0 && slowing < 250.0, "{}", slowing);
+        estimator.update(20.0, now_ms + STOPPED_MS);
+        assert_eq!(estimator.degrees_per_second(now_ms + STOPPED_MS), 0.0);
+    }
+
+    #[test]
+    fn reversal_starts_a_new_estimate() {
+        let mut estimator = VelocityEstimator::default();
+        let mut now_ms = ramp(&mut estimator, 20, 4);
+        for angle in (16..20).rev() {
+            now_ms += 2;
+            estimator.update(angle as f32, now_ms);
+        }
+        // Measured from the last count at 20° (80 ms), not from the forward samples
+        assert_eq!(now_ms, 91);
+        assert_eq!(estimator.degrees_per_second(now_ms), -4.0 * 1000.0 / 11.0);
+    }
+
+    #[test]
+    fn lead_is_distance_covered_during_latency() {
+        assert_eq!(lead_angle(250.0, 20), 5.0);
+        assert_eq!(lead_angle(-250.0, 20), 0.0, "No lead while returning");
+        assert_eq!(lead_angle(250.0, 0), 0.0);
+    }
+}
@@ -1,4 +1,14 @@
-use crate::rotary::{RotaryEncoderState, Settings};
+use crate::alarm::AlarmCode;
+use crate::calibration::{CalibrationCommand, CalibrationPoint};
+use crate::command::{Command, CommandError, CommandSender};
+use crate::feedback::FeedbackError;
+use crate::latency::LatencyStats;
+use crate::machine::MachineState;
+use crate::navigation::StepCommand;
+use crate::program::{EditError, ProgramEdit};
+use crate::results::BendResult;
+use crate::teach::TeachSource;
+use crate::rotary::{BendOptions, OvertravelRecord, RotaryEncoderState, SafeStopReport, Settings, StartError};
 use embedded_svc::io::Write;
 use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration};
 use esp_idf_hal::modem::Modem;
@@ -57,18 +67,50 @@ fn setup_ap_mode(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<st
 #[derive(Serialize, Deserialize)]
 struct SetAnglesRequest {
     angles: Vec<f32>,
+    /// Optional per-bend overrides, matched to `angles` by position
+    #[serde(default)]
+    bends: Vec<BendOptions>,
 }
 
 #[derive(Serialize)]
 struct StatusResponse {
+    machine_state: MachineState,
     active: bool,
+    /// Pause requested, taking effect once the ram is back at the top
+    pause_pending: bool,
     angle: f32,
+    /// Ram speed in degrees per second, positive while the angle increases
+    velocity: f32,
+    /// Degrees before the target the output fires at this speed
+    lead_angle: f32,
     target_angles: Vec<f32>,
     current_target_index: usize,
     output_on: bool,
     target_reached: bool,
     current_run: i32,
     total_runs: i32,
+    interlock_open: bool,
+    alarm_blocking: bool,
+    alarms: Vec<AlarmCode>,
+    last_overtravel: Option<OvertravelRecord>,
+    /// Bends of the current job outside their accept tolerance
+    rejects: u32,
+    last_reject: Option<BendResult>,
+    stalled: bool,
+    idle_ms: u32,
+    safe_stop_active: bool,
+    last_safe_stop: Option<SafeStopReport>,
+    teach_mode: bool,
+    /// Peak of the last manual stroke in teach mode
+    teach_peak: Option<f32>,
+    calibration_points: [Option<CalibrationPoint>; 2],
+}
+
+#[derive(Serialize)]
+struct LatencyResponse {
+    measured: LatencyStats,
+    /// Latency currently used for the early trigger
+    output_latency_ms: u32,
 }
 
 #[derive(Serialize)]
@@ -83,24 +125,65 @@ struct ManualOutputRequest {
     state: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+struct TeachModeRequest {
+    enabled: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct TeachRequest {
+    /// What to store; the last stroke peak when omitted
+    #[serde(default)]
+    source: TeachSource,
+}
+
+#[derive(Deserialize)]
+struct FeedbackRequest {
+    /// Angle of the last bend as measured on the part
+    angle: f32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AlarmAckRequest {
+    /// Alarm to acknowledge; all cleared alarms are acknowledged when omitted
+    code: Option<AlarmCode>,
+}
+
 const SETTINGS_NVS_KEY: &str = "encoder_cfg";
 // Buffer must be large enough for the worst-case serialized Settings JSON.
-// Worst-case compact JSON is ~303 bytes (e.g., "CounterClockwise" + u32::MAX values).
-const SETTINGS_NVS_BUF_SIZE: usize = 512;
+// Worst-case compact JSON is ~970 bytes (e.g., "CounterClockwise" + u32::MAX values + calibration,
+// travel and hysteresis), so leave room for fields added later.
+const SETTINGS_NVS_BUF_SIZE: usize = 2048;
 
-fn load_settings_from_nvs(nvs_partition: &EspDefaultNvsPartition) -> Option<Settings> {
+fn load_settings_from_nvs(nvs_partition: &EspDefaultNvsPartition, encoder_state: &RotaryEncoderState) -> Option<Settings> {
     match esp_idf_svc::nvs::EspNvs::new(nvs_partition.clone(), "storage", true) {
         Ok(nvs) => {
             let mut buf = [0u8; SETTINGS_NVS_BUF_SIZE];
             match nvs.get_raw(SETTINGS_NVS_KEY, &mut buf) {
                 Ok(Some(data)) => {
-                    match serde_json::from_slice::<Settings>(data) {
-                        Ok(settings) => {
-                            info!("Loaded settings from NVS: {:?}", settings);
-                            Some(settings)
-                        }
+                    match Settings::from_json(data) {
+                        Ok(settings) => match settings.validate() {
+                            Ok(()) => {
+                                info!("Loaded settings from NVS: {:?}", settings);
+                                Some(settings)
+                            }
+                            Err(message) => {
+                                error!("Stored settings rejected: {}", message);
+                                encoder_state.raise_alarm(
+                                    AlarmCode::StorageFailure,
+                                    format!("Stored settings are invalid: {}", message),
+                                    encoder_state.uptime_ms(),
+                                );
+                                None
+                            }
+                        },
                         Err(e) => {
                             error!("Failed to deserialize settings from NVS: {:?}", e);
+                            encoder_state.raise_alarm(
+                                AlarmCode::StorageFailure,
+                                format!("Stored settings are invalid: {}", e),
+                                encoder_state.uptime_ms(),
+                            );
                             None
                         }
                     }
@@ -111,17 +194,44 @@ fn load_settings_from_nvs(nvs_partition: &EspDefaultNvsPartition) -> Option<Sett
                 }
                 Err(e) => {
                     error!("Failed to read settings from NVS: {:?}", e);
+                    encoder_state.raise_alarm(
+                        AlarmCode::StorageFailure,
+                        format!("Failed to read settings: {:?}", e),
+                        encoder_state.uptime_ms(),
+                    );
                     None
                 }
             }
         }
         Err(e) => {
             error!("Failed to open NVS namespace: {:?}", e);
+            encoder_state.raise_alarm(
+                AlarmCode::StorageFailure,
+                format!("Failed to open NVS namespace: {:?}", e),
+                encoder_state.uptime_ms(),
+            );
             None
         }
     }
 }
 
+/// HTTP status for a command the control task did not accept
+fn command_error_status(e: &CommandError) -> (u16, &'static str) {
+    match e {
+        CommandError::Unavailable => (503, "Service Unavailable"),
+        CommandError::Start(StartError::OutOfTravel { .. } | StartError::InvalidOutputMode { .. })
+        | CommandError::Edit(EditError::OutOfTravel(_) | EditError::InvalidOutputMode { .. })
+        | CommandError::Feedback(FeedbackError::InvalidAngle | FeedbackError::OutOfTravel(_)) => {
+            (400, "Bad Request")
+        }
+        _ => (409, "Conflict"),
+    }
+}
+
+fn command_error_body(e: &CommandError) -> String {
+    serde_json::json!({ "status": "error", "message": e.to_string() }).to_string()
+}
+
 fn save_settings_to_nvs(settings: &Settings) -> anyhow::Result<()> {
     use esp_idf_sys::{nvs_open, nvs_set_blob, nvs_commit, nvs_close, nvs_handle_t, nvs_open_mode_t_NVS_READWRITE};
     use std::ffi::CString;
@@ -163,6 +273,7 @@ fn save_settings_to_nvs(settings: &Settings) -> anyhow::Result<()> {
 
 pub fn start_webserver(
     encoder_state: RotaryEncoderState,
+    commands: CommandSender,
     modem: Modem,
 ) -> anyhow::Result<()> {
     info!("Initializing WiFi...");
@@ -171,8 +282,10 @@ pub fn start_webserver(
     let nvs = EspDefaultNvsPartition::take()?;
 
     // Load settings from NVS if available
-    if let Some(settings) = load_settings_from_nvs(&nvs) {
-        encoder_state.set_settings(settings);
+    if let Some(settings) = load_settings_from_nvs(&nvs, &encoder_state) {
+        if let Err(e) = commands.send(Command::UpdateSettings(settings)) {
+            error!("Failed to apply stored settings: {}", e);
+        }
     }
 
     let mut wifi = BlockingWifi::wrap(
@@ -245,15 +358,36 @@ pub fn start_webserver(
     // API: Get status
     let encoder_state_status = encoder_state_handlers.clone();
     server.fn_handler("/api/status", embedded_svc::http::Method::Get, move |req| {
+        // Live values come from the control loop's snapshot, so they are consistent
+        // with each other and reading them never touches the control state
+        let snapshot = encoder_state_status.get_snapshot();
+        let (rejects, last_reject) = encoder_state_status.get_rejects();
         let status = StatusResponse {
-            active: encoder_state_status.is_active(),
-            angle: encoder_state_status.get_angle(),
+            machine_state: snapshot.machine_state,
+            active: snapshot.machine_state.is_running(),
+            pause_pending: snapshot.pause_pending,
+            angle: snapshot.angle,
+            velocity: snapshot.degrees_per_second,
+            lead_angle: snapshot.lead_angle,
             target_angles: encoder_state_status.get_target_angles(),
-            current_target_index: encoder_state_status.get_current_target_index(),
-            output_on: encoder_state_status.is_output_on(),
-            target_reached: encoder_state_status.is_target_reached(),
-            current_run: encoder_state_status.get_current_run(),
-            total_runs: encoder_state_status.get_total_runs(),
+            current_target_index: snapshot.current_target_index,
+            output_on: snapshot.output_on,
+            target_reached: snapshot.machine_state == MachineState::Returning,
+            current_run: snapshot.current_run,
+            total_runs: snapshot.total_runs,
+            interlock_open: snapshot.interlock_open,
+            alarm_blocking: encoder_state_status.is_alarm_blocking(),
+            alarms: encoder_state_status.get_latched_alarms(),
+            last_overtravel: encoder_state_status.get_last_overtravel(),
+            rejects,
+            last_reject,
+            stalled: snapshot.stalled,
+            idle_ms: snapshot.idle_ms,
+            safe_stop_active: snapshot.machine_state == MachineState::SafeStop,
+            last_safe_stop: encoder_state_status.get_last_safe_stop(),
+            teach_mode: snapshot.teach_mode,
+            teach_peak: snapshot.teach_peak,
+            calibration_points: snapshot.calibration_points,
         };
 
         let json = serde_json::to_string(&status)
@@ -268,8 +402,9 @@ pub fn start_webserver(
 
     // API: Set angles
     let encoder_state_set = encoder_state_handlers.clone();
+    let commands_set = commands.clone();
     server.fn_handler("/api/set", embedded_svc::http::Method::Post, move |mut req| {
-        let mut buf = vec![0u8; 1024]; // Increased from 512 to support more angles
+        let mut buf = vec![0u8; 2048]; // Room for per-bend options alongside the angles
         let len = req.read(&mut buf)?;
         
         match serde_json::from_slice::<SetAnglesRequest>(&buf[..len]) {
@@ -282,10 +417,19 @@ pub fn start_webserver(
                     info!("🔍 DEBUG: Start button clicked - Target angles: {:?}, Current angle: {:.1}°", request.angles, current_angle);
                 }
                 
-                encoder_state_set.set_target_angles(request.angles);
-                
-                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
-                    .write_all(b"{\"status\":\"ok\"}")?;
+                let start = Command::Start { angles: request.angles, bends: request.bends };
+                match commands_set.send(start) {
+                    Ok(()) => {
+                        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                            .write_all(b"{\"status\":\"ok\"}")?;
+                    }
+                    Err(e) => {
+                        warn!("Start rejected: {}", e);
+                        let (status, reason) = command_error_status(&e);
+                        req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                            .write_all(command_error_body(&e).as_bytes())?;
+                    }
+                }
             }
             Err(e) => {
                 error!("Failed to parse request: {:?}", e);
@@ -298,13 +442,135 @@ pub fn start_webserver(
     })?;
 
     // API: Stop encoder
-    let encoder_state_stop = encoder_state_handlers.clone();
+    let commands_stop = commands.clone();
     server.fn_handler("/api/stop", embedded_svc::http::Method::Post, move |req| {
         info!("Stopping encoder");
-        encoder_state_stop.stop();
-        
-        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
-            .write_all(b"{\"status\":\"ok\"}")?;
+        match commands_stop.send(Command::Stop) {
+            Ok(()) => {
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                error!("Stop failed: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Abort an in-progress safe stop (output turns OFF without waiting for the ram)
+    let commands_abort = commands.clone();
+    server.fn_handler("/api/stop/abort", embedded_svc::http::Method::Post, move |req| {
+        match commands_abort.send(Command::AbortSafeStop) {
+            Ok(()) => {
+                warn!("Safe stop aborted");
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Pause the running job at the top of the current stroke
+    let commands_pause = commands.clone();
+    server.fn_handler("/api/pause", embedded_svc::http::Method::Post, move |req| {
+        match commands_pause.send(Command::Pause) {
+            Ok(()) => {
+                info!("⏸️ Pause requested");
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Resume a paused job (or cancel a pause still waiting for the top)
+    let commands_resume = commands.clone();
+    server.fn_handler("/api/resume", embedded_svc::http::Method::Post, move |req| {
+        match commands_resume.send(Command::Resume) {
+            Ok(()) => {
+                info!("▶️ Job resumed");
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Repeat, skip or go back within the running program (ram at the top only)
+    let commands_step = commands.clone();
+    server.fn_handler("/api/step", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        match serde_json::from_slice::<StepCommand>(&buf[..len]) {
+            Ok(step) => match commands_step.send(Command::Step(step)) {
+                Ok(()) => {
+                    info!("⏭️ Step navigation: {:?}", step);
+                    req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                        .write_all(b"{\"status\":\"ok\"}")?;
+                }
+                Err(e) => {
+                    warn!("Step navigation rejected: {}", e);
+                    let (status, reason) = command_error_status(&e);
+                    req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                        .write_all(command_error_body(&e).as_bytes())?;
+                }
+            },
+            Err(e) => {
+                error!("Failed to parse step request: {:?}", e);
+                let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                    .write_all(error_msg.as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Change, insert or delete a bend that has not been executed yet
+    let commands_program = commands.clone();
+    server.fn_handler("/api/program/edit", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 512];
+        let len = req.read(&mut buf)?;
+
+        match serde_json::from_slice::<ProgramEdit>(&buf[..len]) {
+            Ok(edit) => match commands_program.send(Command::EditProgram(edit)) {
+                Ok(()) => {
+                    info!("✏️ Program edited");
+                    req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                        .write_all(b"{\"status\":\"ok\"}")?;
+                }
+                Err(e) => {
+                    warn!("Program edit rejected: {}", e);
+                    let (status, reason) = command_error_status(&e);
+                    req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                        .write_all(command_error_body(&e).as_bytes())?;
+                }
+            },
+            Err(e) => {
+                error!("Failed to parse program edit: {:?}", e);
+                let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                    .write_all(error_msg.as_bytes())?;
+            }
+        }
         Ok::<(), anyhow::Error>(())
     })?;
 
@@ -381,12 +647,21 @@ pub fn start_webserver(
 
     // API: Save settings
     let encoder_state_save_settings = encoder_state_handlers.clone();
+    let commands_save_settings = commands.clone();
     server.fn_handler("/api/settings", embedded_svc::http::Method::Post, move |mut req| {
-        let mut buf = vec![0u8; 512];
+        let mut buf = vec![0u8; SETTINGS_NVS_BUF_SIZE];
         let len = req.read(&mut buf)?;
         
-        match serde_json::from_slice::<Settings>(&buf[..len]) {
+        match Settings::from_json(&buf[..len]) {
             Ok(mut settings) => {
+                if let Err(message) = settings.validate() {
+                    warn!("Settings rejected: {}", message);
+                    let body = serde_json::json!({ "status": "error", "message": message }).to_string();
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                        .write_all(body.as_bytes())?;
+                    return Ok(());
+                }
+
                 // Validate and clamp update_rate_ms to acceptable range (1-200ms)
                 let original_update_rate = settings.update_rate_ms;
                 settings.update_rate_ms = settings.update_rate_ms.clamp(1, 200);
@@ -395,17 +670,32 @@ pub fn start_webserver(
                 }
                 
                 info!("Saving settings: {:?}", settings);
-                encoder_state_save_settings.set_settings(settings.clone());
+                if let Err(e) = commands_save_settings.send(Command::UpdateSettings(settings.clone())) {
+                    error!("Settings not applied: {}", e);
+                    let (status, reason) = command_error_status(&e);
+                    req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                        .write_all(command_error_body(&e).as_bytes())?;
+                    return Ok(());
+                }
                 
                 // Try to save to NVS
                 match save_settings_to_nvs(&settings) {
                     Ok(_) => {
                         info!("Settings saved to NVS");
+                        encoder_state_save_settings.clear_alarm_condition(
+                            AlarmCode::StorageFailure,
+                            encoder_state_save_settings.uptime_ms(),
+                        );
                         req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
                             .write_all(b"{\"status\":\"ok\"}")?;
                     }
                     Err(e) => {
                         error!("Failed to save settings to NVS: {:?}", e);
+                        encoder_state_save_settings.raise_alarm(
+                            AlarmCode::StorageFailure,
+                            format!("Failed to save settings: {}", e),
+                            encoder_state_save_settings.uptime_ms(),
+                        );
                         req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
                             .write_all(b"{\"status\":\"ok\",\"warning\":\"Settings applied but not saved to flash\"}")?;
                     }
@@ -422,7 +712,7 @@ pub fn start_webserver(
     })?;
 
     // API: Manual output control
-    let encoder_state_manual_output = encoder_state_handlers.clone();
+    let commands_manual_output = commands.clone();
     server.fn_handler("/api/output/manual", embedded_svc::http::Method::Post, move |mut req| {
         let mut buf = [0u8; 128];
         let len = req.read(&mut buf)?;
@@ -430,13 +720,247 @@ pub fn start_webserver(
         match serde_json::from_slice::<ManualOutputRequest>(&buf[..len]) {
             Ok(request) => {
                 info!("Manual output control: state={}", request.state);
-                encoder_state_manual_output.set_manual_output(request.state);
-                
+                match commands_manual_output.send(Command::SetManual(request.state)) {
+                    Ok(()) => {
+                        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                            .write_all(b"{\"status\":\"ok\"}")?;
+                    }
+                    Err(e) => {
+                        warn!("Manual output rejected: {}", e);
+                        let (status, reason) = command_error_status(&e);
+                        req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                            .write_all(command_error_body(&e).as_bytes())?;
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to parse manual output request: {:?}", e);
+                let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                    .write_all(error_msg.as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Get interlock state and history
+    let encoder_state_interlocks = encoder_state_handlers.clone();
+    server.fn_handler("/api/interlocks", embedded_svc::http::Method::Get, move |req| {
+        let status = encoder_state_interlocks.get_interlock_status();
+
+        let json = serde_json::to_string(&status)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize interlock status: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Get the control event log
+    let encoder_state_events = encoder_state_handlers.clone();
+    server.fn_handler("/api/events", embedded_svc::http::Method::Get, move |req| {
+        let events = encoder_state_events.get_events();
+
+        let json = serde_json::to_string(&events)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize events: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Peak angle and timing of every completed bend of the current job
+    let encoder_state_results = encoder_state_handlers.clone();
+    server.fn_handler("/api/results", embedded_svc::http::Method::Get, move |req| {
+        let report = encoder_state_results.get_results();
+
+        let json = serde_json::to_string(&report)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize results: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Measured time from the output firing to the ram reversing
+    let encoder_state_latency = encoder_state_handlers.clone();
+    server.fn_handler("/api/diagnostics/latency", embedded_svc::http::Method::Get, move |req| {
+        let response = LatencyResponse {
+            measured: encoder_state_latency.get_snapshot().latency,
+            output_latency_ms: encoder_state_latency.get_settings().output_latency_ms,
+        };
+
+        let json = serde_json::to_string(&response)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize latency: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Clear the latency statistics, e.g. after a valve change
+    let commands_latency_reset = commands.clone();
+    server.fn_handler("/api/diagnostics/latency/reset", embedded_svc::http::Method::Post, move |req| {
+        match commands_latency_reset.send(Command::ResetLatency) {
+            Ok(()) => {
                 req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
                     .write_all(b"{\"status\":\"ok\"}")?;
             }
             Err(e) => {
-                error!("Failed to parse manual output request: {:?}", e);
+                warn!("Latency reset rejected: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Get latched alarms and alarm history
+    let encoder_state_alarms = encoder_state_handlers.clone();
+    server.fn_handler("/api/alarms", embedded_svc::http::Method::Get, move |req| {
+        let status = encoder_state_alarms.get_alarm_status();
+
+        let json = serde_json::to_string(&status)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize alarm status: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Acknowledge one alarm, or every cleared alarm when no code is given
+    let commands_alarm_ack = commands.clone();
+    server.fn_handler("/api/alarms/ack", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        let request = if len == 0 {
+            AlarmAckRequest::default()
+        } else {
+            match serde_json::from_slice::<AlarmAckRequest>(&buf[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    error!("Failed to parse alarm acknowledge request: {:?}", e);
+                    let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                        .write_all(error_msg.as_bytes())?;
+                    return Ok(());
+                }
+            }
+        };
+
+        match commands_alarm_ack.send(Command::Acknowledge(request.code)) {
+            Ok(()) => {
+                info!("Alarms acknowledged");
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                warn!("Alarm acknowledge rejected: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Two-point calibration (capture a point, apply or clear the calibration)
+    let commands_calibration = commands.clone();
+    let encoder_state_calibration = encoder_state_handlers.clone();
+    server.fn_handler("/api/calibration", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        let step = match serde_json::from_slice::<CalibrationCommand>(&buf[..len]) {
+            Ok(step) => step,
+            Err(e) => {
+                error!("Failed to parse calibration request: {:?}", e);
+                let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                    .write_all(error_msg.as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        if let Err(e) = commands_calibration.send(Command::Calibrate(step)) {
+            warn!("Calibration rejected: {}", e);
+            let (status, reason) = command_error_status(&e);
+            req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                .write_all(command_error_body(&e).as_bytes())?;
+            return Ok(());
+        }
+
+        if step == CalibrationCommand::Apply || step == CalibrationCommand::Clear {
+            // The calibration is part of the settings: persist it like a settings change
+            if let Err(e) = save_settings_to_nvs(&encoder_state_calibration.get_settings()) {
+                error!("Failed to save calibration to NVS: {:?}", e);
+                encoder_state_calibration.raise_alarm(
+                    AlarmCode::StorageFailure,
+                    format!("Failed to save calibration: {}", e),
+                    encoder_state_calibration.uptime_ms(),
+                );
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\",\"warning\":\"Calibration applied but not saved to flash\"}")?;
+                return Ok(());
+            }
+        }
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(b"{\"status\":\"ok\"}")?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Set zero at the current position (loads the configured zero offset)
+    let commands_zero = commands.clone();
+    server.fn_handler("/api/zero", embedded_svc::http::Method::Post, move |req| {
+        match commands_zero.send(Command::SetZero) {
+            Ok(()) => {
+                info!("📍 Zero set");
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                warn!("Set zero rejected: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Enable or disable teach mode (enabling starts a new target list)
+    let commands_teach_mode = commands.clone();
+    server.fn_handler("/api/teach/mode", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        match serde_json::from_slice::<TeachModeRequest>(&buf[..len]) {
+            Ok(request) => match commands_teach_mode.send(Command::SetTeachMode(request.enabled)) {
+                Ok(()) => {
+                    req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                        .write_all(b"{\"status\":\"ok\"}")?;
+                }
+                Err(e) => {
+                    warn!("Teach mode change rejected: {}", e);
+                    let (status, reason) = command_error_status(&e);
+                    req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                        .write_all(command_error_body(&e).as_bytes())?;
+                }
+            },
+            Err(e) => {
+                error!("Failed to parse teach mode request: {:?}", e);
                 let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
                 req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
                     .write_all(error_msg.as_bytes())?;
@@ -445,6 +969,90 @@ pub fn start_webserver(
         Ok::<(), anyhow::Error>(())
     })?;
 
+    // API: Append the last stroke peak (or the current angle) to the target list
+    let commands_teach = commands.clone();
+    server.fn_handler("/api/teach", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        let request = if len == 0 {
+            TeachRequest::default()
+        } else {
+            match serde_json::from_slice::<TeachRequest>(&buf[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    error!("Failed to parse teach request: {:?}", e);
+                    let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                        .write_all(error_msg.as_bytes())?;
+                    return Ok(());
+                }
+            }
+        };
+
+        match commands_teach.send(Command::Teach(request.source)) {
+            Ok(()) => {
+                info!("📐 Target taught ({:?})", request.source);
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                warn!("Teach rejected: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: Submit the measured angle of the last bend to correct its compensation
+    let commands_feedback = commands.clone();
+    server.fn_handler("/api/feedback", embedded_svc::http::Method::Post, move |mut req| {
+        let mut buf = [0u8; 128];
+        let len = req.read(&mut buf)?;
+
+        let request = match serde_json::from_slice::<FeedbackRequest>(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to parse feedback request: {:?}", e);
+                let error_msg = format!(r#"{{"status":"error","message":"Invalid JSON: {}"}}"#, e);
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "application/json")])?
+                    .write_all(error_msg.as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        match commands_feedback.send(Command::Feedback(request.angle)) {
+            Ok(()) => {
+                req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                    .write_all(b"{\"status\":\"ok\"}")?;
+            }
+            Err(e) => {
+                warn!("Feedback rejected: {}", e);
+                let (status, reason) = command_error_status(&e);
+                req.into_response(status, Some(reason), &[("Content-Type", "application/json")])?
+                    .write_all(command_error_body(&e).as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API: History of measured angles and the compensation changes they made
+    let encoder_state_feedback = encoder_state_handlers.clone();
+    server.fn_handler("/api/feedback", embedded_svc::http::Method::Get, move |req| {
+        let history = encoder_state_feedback.get_feedback_history();
+
+        let json = serde_json::to_string(&history)
+            .unwrap_or_else(|e| {
+                error!("Failed to serialize feedback history: {:?}", e);
+                r#"{"error":"serialization_failed"}"#.to_string()
+            });
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
     info!("Web server started at http://{}", ip_address);
     info!("Open this URL in your browser to control the encoder");
 